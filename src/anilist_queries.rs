@@ -1,8 +1,15 @@
 use std::cmp::PartialEq;
+use std::collections::{BTreeMap, HashMap, HashSet};
 
+use async_trait::async_trait;
+use serde::de::DeserializeOwned;
 use serde::{Deserialize, Serialize};
 
-use super::config::AnilistConfig;
+use super::config::{AnilistConfig, ApiUrls};
+use super::error::{Error, Result};
+use super::provider::{EntryValues, ListEntry, ListProvider, Service, User};
+
+const SERVICE: &str = "AniList";
 
 #[derive(Deserialize, Debug)]
 struct UserIdResp {
@@ -37,30 +44,149 @@ pub struct Lists {
     pub lists: Vec<Entries>,
 }
 
+impl Lists {
+    /// Every entry once. An entry shows up in more than one list when it's in a custom list
+    pub fn unique_entries(&self) -> Vec<&Entry> {
+        let mut seen = HashSet::new();
+        self.lists
+            .iter()
+            .flat_map(|list| list.entries.iter())
+            .filter(|entry| seen.insert(entry.media.id))
+            .collect()
+    }
+
+    pub fn list_entries(&self) -> Vec<ListEntry> {
+        self.unique_entries()
+            .into_iter()
+            .map(ListEntry::from)
+            .collect()
+    }
+}
+
 #[derive(Deserialize, Debug)]
+#[serde(rename_all(deserialize = "camelCase"))]
 pub struct Entries {
+    pub name: String,
+    pub is_custom_list: bool,
+    /// Completed can be split into a list per format, so there can be more than one list per status
+    pub is_split_completed_list: bool,
+    /// `None` for custom lists
+    pub status: Option<MediaListStatus>,
     pub entries: Vec<Entry>,
 }
 
 #[derive(Deserialize, Serialize, Debug)]
+#[serde(rename_all(deserialize = "camelCase"))]
 pub struct Entry {
     pub status: MediaListStatus,
     pub score: f32,
     pub progress: u32,
+    pub progress_volumes: Option<u32>,
+    /// How many times it's been rewatched or reread
+    pub repeat: Option<u32>,
+    pub notes: Option<String>,
+    pub private: Option<bool>,
+    pub hidden_from_status_lists: Option<bool>,
+    pub custom_lists: Option<Vec<CustomList>>,
+    /// Sub-score name to score. Empty unless advanced scoring is turned on
+    pub advanced_scores: Option<BTreeMap<String, f32>>,
+    pub started_at: FuzzyDate,
+    pub completed_at: FuzzyDate,
+    /// Unix timestamps. Can be missing on really old entries
+    pub created_at: Option<i64>,
+    pub updated_at: Option<i64>,
     pub media: Media,
 }
 
+impl From<&Entry> for ListEntry {
+    fn from(entry: &Entry) -> ListEntry {
+        ListEntry {
+            anilist_id: Some(entry.media.id),
+            mal_id: entry.media.id_mal,
+            title: entry.media.title.user_preferred.clone(),
+            status: entry.status,
+            score: entry.score,
+            mal_score: None,
+            progress: entry.progress,
+            progress_volumes: entry.progress_volumes,
+            repeat: entry.repeat.unwrap_or(0),
+            // AniList sends an empty string once notes have been cleared
+            notes: entry.notes.clone().filter(|notes| !notes.is_empty()),
+            started_at: entry.started_at,
+            completed_at: entry.completed_at,
+            updated_at: entry.updated_at,
+            format: Some(entry.media.format),
+            episodes: entry.media.episodes,
+            chapters: entry.media.chapters,
+            start_year: entry.media.start_date.year,
+            private: entry.private.unwrap_or(false),
+            hidden_from_status_lists: entry.hidden_from_status_lists.unwrap_or(false),
+            custom_lists: entry
+                .custom_lists
+                .iter()
+                .flatten()
+                .filter(|list| list.enabled)
+                .map(|list| list.name.clone())
+                .collect(),
+            created_at: entry.created_at,
+            advanced_scores: entry.advanced_scores.clone().unwrap_or_default(),
+        }
+    }
+}
+
+/// A date where any part can be missing, e.g. only the year is known
+#[derive(Deserialize, Serialize, Debug, Clone, Copy, PartialEq, Default)]
+pub struct FuzzyDate {
+    pub year: Option<i32>,
+    pub month: Option<u32>,
+    pub day: Option<u32>,
+}
+
+impl FuzzyDate {
+    pub fn is_empty(&self) -> bool {
+        self.year.is_none() && self.month.is_none() && self.day.is_none()
+    }
+}
+
+/// Whether the entry is in one of the user's custom lists
+#[derive(Deserialize, Serialize, Debug)]
+pub struct CustomList {
+    pub name: String,
+    pub enabled: bool,
+}
+
+// the aliases let things this program wrote (backups and plans) be read back in
 #[derive(Deserialize, Serialize, Debug, PartialEq, Copy, Clone)]
 #[serde(rename_all(deserialize = "SCREAMING_SNAKE_CASE", serialize = "PascalCase"))]
 pub enum MediaListStatus {
+    #[serde(alias = "Completed")]
     Completed,
+    #[serde(alias = "Current")]
     Current,
+    #[serde(alias = "Planning")]
     Planning,
+    #[serde(alias = "Dropped")]
     Dropped,
+    #[serde(alias = "Paused")]
     Paused,
+    #[serde(alias = "Repeating")]
     Repeating,
 }
 
+impl MediaListStatus {
+    /// The name of the status in AniList's GraphQL schema
+    pub fn graphql_name(&self) -> &'static str {
+        match self {
+            MediaListStatus::Completed => "COMPLETED",
+            MediaListStatus::Current => "CURRENT",
+            MediaListStatus::Planning => "PLANNING",
+            MediaListStatus::Dropped => "DROPPED",
+            MediaListStatus::Paused => "PAUSED",
+            MediaListStatus::Repeating => "REPEATING",
+        }
+    }
+}
+
 #[derive(Deserialize, Serialize, Debug)]
 #[serde(rename_all(deserialize = "camelCase", serialize = "snake_case"))]
 pub struct Media {
@@ -70,6 +196,7 @@ pub struct Media {
     pub format: MediaFormat,
     pub episodes: Option<u32>,
     pub chapters: Option<u32>,
+    pub start_date: FuzzyDate,
 }
 
 #[derive(Deserialize, Serialize, Debug)]
@@ -78,6 +205,8 @@ pub struct Title {
     pub user_preferred: String,
 }
 
+// the variants are serialized as-is into the GraphQL variables
+#[allow(clippy::upper_case_acronyms)]
 #[derive(Deserialize, Serialize, Debug, Clone, Copy, PartialEq)]
 pub enum MediaType {
     ANIME,
@@ -87,15 +216,25 @@ pub enum MediaType {
 #[derive(Deserialize, Serialize, Debug, Clone, Copy)]
 #[serde(rename_all(deserialize = "SCREAMING_SNAKE_CASE", serialize = "PascalCase"))]
 pub enum MediaFormat {
+    #[serde(alias = "Tv")]
     Tv,
+    #[serde(alias = "TvShort")]
     TvShort,
+    #[serde(alias = "Movie")]
     Movie,
+    #[serde(alias = "Special")]
     Special,
+    #[serde(alias = "Ova")]
     Ova,
+    #[serde(alias = "Ona")]
     Ona,
+    #[serde(alias = "Music")]
     Music,
+    #[serde(alias = "Manga")]
     Manga,
+    #[serde(alias = "Novel")]
     Novel,
+    #[serde(alias = "OneShot")]
     OneShot,
 }
 
@@ -108,38 +247,160 @@ mutation {
 }
 ";
 
-pub async fn get_user_id(access_token: String) -> UserData {
-    let auth_header = format!("Bearer {}", access_token);
-
+pub async fn get_user_id(urls: &ApiUrls, access_token: String) -> Result<UserData> {
     let json = serde_json::json!({ "query": GET_USER_ID });
 
     let client = reqwest::Client::new();
-    let res = client
-        .post("https://graphql.anilist.co")
-        .header("Authorization", auth_header)
-        .header("Content-Type", "application/json")
-        .header("Accept", "application/json")
-        .body(json.to_string())
-        .send()
-        .await
-        .unwrap()
-        .text()
-        .await
-        .unwrap();
+    let result: UserIdResp = post_graphql(&client, urls, &access_token, &json).await?;
+    Ok(result.data.update_user)
+}
+
+/// How the user scores entries. Scores are always sent and received in this format
+#[derive(Deserialize, Serialize, Debug, Clone, Copy, PartialEq)]
+pub enum ScoreFormat {
+    /// 0 to 100
+    #[serde(rename = "POINT_100")]
+    Point100,
+    /// 0 to 10 with one decimal place
+    #[serde(rename = "POINT_10_DECIMAL")]
+    Point10Decimal,
+    /// 0 to 10
+    #[serde(rename = "POINT_10")]
+    Point10,
+    /// 0 to 5 stars
+    #[serde(rename = "POINT_5")]
+    Point5,
+    /// 1 to 3 smileys
+    #[serde(rename = "POINT_3")]
+    Point3,
+}
+
+/// AniList's default for new accounts
+impl Default for ScoreFormat {
+    fn default() -> ScoreFormat {
+        ScoreFormat::Point10
+    }
+}
+
+#[derive(Deserialize, Debug)]
+struct ViewerResp {
+    data: ViewerData,
+}
 
-    let result: UserIdResp = serde_json::from_str(&res).unwrap();
-    println!("{}", result.data.update_user.id);
-    result.data.update_user
+#[derive(Deserialize, Debug)]
+#[serde(rename_all(deserialize = "PascalCase"))]
+struct ViewerData {
+    viewer: Viewer,
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(rename_all(deserialize = "camelCase"))]
+struct Viewer {
+    media_list_options: MediaListOptions,
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(rename_all(deserialize = "camelCase"))]
+struct MediaListOptions {
+    score_format: ScoreFormat,
+    /// Only asked for by `get_advanced_scoring`
+    #[serde(default)]
+    anime_list: Option<MediaListTypeOptions>,
+    #[serde(default)]
+    manga_list: Option<MediaListTypeOptions>,
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(rename_all(deserialize = "camelCase"))]
+struct MediaListTypeOptions {
+    advanced_scoring: Option<Vec<String>>,
+}
+
+const GET_SCORE_FORMAT: &str = "
+query {
+    Viewer {
+        mediaListOptions {
+            scoreFormat
+        }
+    }
+}
+";
+
+pub async fn get_score_format(urls: &ApiUrls, access_token: &str) -> Result<ScoreFormat> {
+    let json = serde_json::json!({ "query": GET_SCORE_FORMAT });
+
+    let client = reqwest::Client::new();
+    let result: ViewerResp = post_graphql(&client, urls, access_token, &json).await?;
+    Ok(result.data.viewer.media_list_options.score_format)
+}
+
+const GET_ADVANCED_SCORING: &str = "
+query {
+    Viewer {
+        mediaListOptions {
+            scoreFormat
+            animeList {
+                advancedScoring
+            }
+            mangaList {
+                advancedScoring
+            }
+        }
+    }
+}
+";
+
+/// The names of the user's advanced scores for `list_type`, in the order AniList takes them
+pub async fn get_advanced_scoring(
+    urls: &ApiUrls,
+    access_token: &str,
+    list_type: MediaType,
+) -> Result<Vec<String>> {
+    let json = serde_json::json!({ "query": GET_ADVANCED_SCORING });
+
+    let client = reqwest::Client::new();
+    let result: ViewerResp = post_graphql(&client, urls, access_token, &json).await?;
+    let options = result.data.viewer.media_list_options;
+    let list_options = match list_type {
+        MediaType::ANIME => options.anime_list,
+        MediaType::MANGA => options.manga_list,
+    };
+    Ok(list_options
+        .and_then(|list_options| list_options.advanced_scoring)
+        .unwrap_or_default())
 }
 
 const GET_LIST: &str = "
 query ($id: Int, $list_type: MediaType) {
 	MediaListCollection(userId: $id, type: $list_type) {
     lists {
+      name
+      isCustomList
+      isSplitCompletedList
+      status
       entries {
         status
         score
         progress
+        progressVolumes
+        repeat
+        notes
+        private
+        hiddenFromStatusLists
+        customLists(asArray: true)
+        advancedScores
+        startedAt {
+          year
+          month
+          day
+        }
+        completedAt {
+          year
+          month
+          day
+        }
+        createdAt
+        updatedAt
         media {
           id
           idMal
@@ -149,6 +410,9 @@ query ($id: Int, $list_type: MediaType) {
           format
           episodes
           chapters
+          startDate {
+            year
+          }
         }
       }
     }
@@ -156,28 +420,281 @@ query ($id: Int, $list_type: MediaType) {
 }
 ";
 
-pub async fn get_list(config: &AnilistConfig, list_type: MediaType) -> Lists {
-    let auth_header = format!("Bearer {}", config.access_token);
-
+pub async fn get_list(
+    config: &AnilistConfig,
+    urls: &ApiUrls,
+    list_type: MediaType,
+) -> Result<Lists> {
     let json = serde_json::json!({
         "query": GET_LIST,
         "variables" : {"id": config.user_id, "list_type": list_type}
     });
 
     let client = reqwest::Client::new();
+    let result: ListResp = post_graphql(&client, urls, &config.access_token, &json).await?;
+    Ok(result.data.media_list_collection)
+}
+
+#[derive(Deserialize, Debug)]
+struct MediaIdsResp {
+    data: MediaIdsPage,
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(rename_all(deserialize = "PascalCase"))]
+struct MediaIdsPage {
+    page: MediaIds,
+}
+
+#[derive(Deserialize, Debug)]
+struct MediaIds {
+    media: Vec<MediaId>,
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(rename_all(deserialize = "camelCase"))]
+struct MediaId {
+    id: u32,
+    id_mal: Option<u32>,
+}
+
+const GET_MEDIA_IDS: &str = "
+query ($ids_mal: [Int], $list_type: MediaType) {
+  Page(perPage: 50) {
+    media(idMal_in: $ids_mal, type: $list_type) {
+      id
+      idMal
+    }
+  }
+}
+";
+
+/// Finds the AniList ids for a set of MAL ids, keyed by MAL id.
+/// MAL ids that AniList doesn't know about are left out
+pub async fn get_media_ids(
+    config: &AnilistConfig,
+    urls: &ApiUrls,
+    ids_mal: &[u32],
+    list_type: MediaType,
+) -> Result<HashMap<u32, u32>> {
+    let client = reqwest::Client::new();
+
+    let mut ids = HashMap::new();
+    // a page can only hold 50 media
+    for chunk in ids_mal.chunks(50) {
+        let json = serde_json::json!({
+            "query": GET_MEDIA_IDS,
+            "variables" : {"ids_mal": chunk, "list_type": list_type}
+        });
+
+        let result: MediaIdsResp = post_graphql(&client, urls, &config.access_token, &json).await?;
+        for media in result.data.page.media {
+            if let Some(id_mal) = media.id_mal {
+                ids.insert(id_mal, media.id);
+            }
+        }
+    }
+    Ok(ids)
+}
+
+#[derive(Deserialize, Debug)]
+struct ErrorResp {
+    errors: Vec<GraphQLError>,
+}
+
+#[derive(Deserialize, Debug)]
+struct GraphQLError {
+    message: String,
+    status: Option<u16>,
+}
+
+/// Sends a GraphQL request and parses the response.
+/// GraphQL errors are returned as `Error::Api`, or `Error::AuthExpired` if the token was rejected
+async fn post_graphql<T: DeserializeOwned>(
+    client: &reqwest::Client,
+    urls: &ApiUrls,
+    access_token: &str,
+    json: &serde_json::Value,
+) -> Result<T> {
     let res = client
-        .post("https://graphql.anilist.co")
-        .header("Authorization", auth_header)
+        .post(&urls.anilist_graphql)
+        .header("Authorization", format!("Bearer {}", access_token))
         .header("Content-Type", "application/json")
         .header("Accept", "application/json")
         .body(json.to_string())
         .send()
-        .await
-        .unwrap()
+        .await?
         .text()
-        .await
-        .unwrap();
+        .await?;
+
+    if let Ok(error) = serde_json::from_str::<ErrorResp>(&res) {
+        // an expired or revoked token comes back as "Invalid token"
+        if error
+            .errors
+            .iter()
+            .any(|e| e.status == Some(401) || e.message == "Invalid token")
+        {
+            return Err(Error::AuthExpired(SERVICE));
+        }
+        let messages: Vec<String> = error.errors.into_iter().map(|e| e.message).collect();
+        return Err(Error::Api(SERVICE, messages.join(". ")));
+    }
+    serde_json::from_str(&res).map_err(|error| Error::unexpected_response(SERVICE, error))
+}
+
+const SAVE_MEDIA_LIST_ENTRY: &str = "
+mutation ($media_id: Int, $status: MediaListStatus, $score: Float, $progress: Int, $progress_volumes: Int, $repeat: Int, $started_at: FuzzyDateInput, $completed_at: FuzzyDateInput, $notes: String, $private: Boolean, $hidden_from_status_lists: Boolean, $custom_lists: [String], $advanced_scores: [Float]) {
+  SaveMediaListEntry(mediaId: $media_id, status: $status, score: $score, progress: $progress, progressVolumes: $progress_volumes, repeat: $repeat, startedAt: $started_at, completedAt: $completed_at, notes: $notes, private: $private, hiddenFromStatusLists: $hidden_from_status_lists, customLists: $custom_lists, advancedScores: $advanced_scores) {
+    id
+  }
+}
+";
+
+/// Creates or updates the list entry for `media_id`.
+/// Fields that are only on AniList are left as they are when they're `None`
+pub async fn update_entry(
+    config: &AnilistConfig,
+    urls: &ApiUrls,
+    list_type: MediaType,
+    media_id: u32,
+    values: &EntryValues,
+) -> Result<()> {
+    // AniList takes advanced scores as a list in the order of the user's categories.
+    // Categories the user doesn't have any more are dropped
+    let advanced_scores = match &values.advanced_scores {
+        Some(advanced_scores) if !advanced_scores.is_empty() => {
+            let names = get_advanced_scoring(urls, &config.access_token, list_type).await?;
+            let scores: Vec<f32> = names
+                .iter()
+                .map(|name| advanced_scores.get(name).copied().unwrap_or(0.0))
+                .collect();
+            Some(scores).filter(|scores| !scores.is_empty())
+        }
+        _ => None,
+    };
+    let json = serde_json::json!({
+        "query": SAVE_MEDIA_LIST_ENTRY,
+        "variables": {
+            "media_id": media_id,
+            "status": values.status.graphql_name(),
+            "score": values.score,
+            "progress": values.progress,
+            "progress_volumes": values.progress_volumes,
+            "repeat": values.repeat,
+            // a date with every part null clears it
+            "started_at": values.started_at,
+            "completed_at": values.completed_at,
+            // null would leave the notes as they are, so they're cleared with an empty string
+            "notes": values.notes.as_deref().unwrap_or(""),
+            "private": values.private,
+            "hidden_from_status_lists": values.hidden_from_status_lists,
+            "custom_lists": values.custom_lists,
+            "advanced_scores": advanced_scores,
+        }
+    });
+
+    let client = reqwest::Client::new();
+    let _: serde_json::Value = post_graphql(&client, urls, &config.access_token, &json).await?;
+    Ok(())
+}
 
-    let result: ListResp = serde_json::from_str(&res).unwrap();
-    result.data.media_list_collection
+#[derive(Deserialize, Debug)]
+struct MediaListResp {
+    data: MediaListData,
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(rename_all(deserialize = "PascalCase"))]
+struct MediaListData {
+    media_list: MediaListId,
+}
+
+#[derive(Deserialize, Debug)]
+struct MediaListId {
+    id: u32,
+}
+
+const GET_MEDIA_LIST_ID: &str = "
+query ($user_id: Int, $media_id: Int) {
+  MediaList(userId: $user_id, mediaId: $media_id) {
+    id
+  }
+}
+";
+
+const DELETE_MEDIA_LIST_ENTRY: &str = "
+mutation ($id: Int) {
+  DeleteMediaListEntry(id: $id) {
+    deleted
+  }
+}
+";
+
+/// Removes `media_id` from the user's list
+pub async fn delete_entry(config: &AnilistConfig, urls: &ApiUrls, media_id: u32) -> Result<()> {
+    let client = reqwest::Client::new();
+
+    // deleting goes by the list entry's id, not the media's
+    let json = serde_json::json!({
+        "query": GET_MEDIA_LIST_ID,
+        "variables": {"user_id": config.user_id, "media_id": media_id}
+    });
+    let result: MediaListResp = post_graphql(&client, urls, &config.access_token, &json).await?;
+
+    let json = serde_json::json!({
+        "query": DELETE_MEDIA_LIST_ENTRY,
+        "variables": {"id": result.data.media_list.id}
+    });
+    let _: serde_json::Value = post_graphql(&client, urls, &config.access_token, &json).await?;
+    Ok(())
+}
+
+pub struct AnilistClient<'a> {
+    pub config: &'a AnilistConfig,
+    pub urls: &'a ApiUrls,
+}
+
+impl AnilistClient<'_> {
+    /// See `get_media_ids`
+    pub async fn media_ids(
+        &self,
+        ids_mal: &[u32],
+        list_type: MediaType,
+    ) -> Result<HashMap<u32, u32>> {
+        get_media_ids(self.config, self.urls, ids_mal, list_type).await
+    }
+}
+
+#[async_trait]
+impl ListProvider for AnilistClient<'_> {
+    fn service(&self) -> Service {
+        Service::Anilist
+    }
+
+    async fn whoami(&self) -> Result<User> {
+        let user = get_user_id(self.urls, self.config.access_token.clone()).await?;
+        Ok(User {
+            id: user.id,
+            name: user.name,
+        })
+    }
+
+    async fn fetch_list(&self, list_type: MediaType) -> Result<Vec<ListEntry>> {
+        Ok(get_list(self.config, self.urls, list_type)
+            .await?
+            .list_entries())
+    }
+
+    async fn update_entry(
+        &self,
+        list_type: MediaType,
+        id: u32,
+        values: &EntryValues,
+    ) -> Result<()> {
+        update_entry(self.config, self.urls, list_type, id, values).await
+    }
+
+    async fn delete_entry(&self, _: MediaType, id: u32) -> Result<()> {
+        delete_entry(self.config, self.urls, id).await
+    }
 }
@@ -3,32 +3,124 @@ use std::env;
 use std::fs;
 use std::io::prelude::*;
 use std::io::ErrorKind;
+#[cfg(unix)]
+use std::os::unix::fs::{OpenOptionsExt, PermissionsExt};
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
 
 use serde::{Deserialize, Serialize};
 
-use super::anilist_queries;
+use super::anilist_queries::{self, ScoreFormat};
+use super::error::{Error, Result};
+use super::mal_queries;
+use super::scores::{Rounding, ScoreConversion};
 
-#[derive(Deserialize, Debug, Serialize)]
+#[derive(Deserialize, Debug, Serialize, Default)]
 pub struct TomlConfig {
     pub anilist: Option<AnilistConfig>,
     pub myanimelist: Option<MALConfig>,
+    pub backup: Option<BackupConfig>,
+    pub sync: Option<SyncConfig>,
+    pub api: Option<ApiUrls>,
+}
+
+impl TomlConfig {
+    /// How scores are converted for MAL, from the AniList score format and the `[sync]` section
+    pub fn score_conversion(&self) -> ScoreConversion {
+        ScoreConversion {
+            format: self
+                .anilist
+                .as_ref()
+                .and_then(|anilist| anilist.score_format)
+                .unwrap_or_default(),
+            rounding: self
+                .sync
+                .as_ref()
+                .map(|sync| sync.score_rounding)
+                .unwrap_or_default(),
+        }
+    }
+}
+
+#[derive(Deserialize, Debug, Serialize, Default)]
+pub struct BackupConfig {
+    /// Where backups are saved when `--output` isn't given
+    pub directory: Option<PathBuf>,
+}
+
+#[derive(Deserialize, Debug, Serialize, Default)]
+pub struct SyncConfig {
+    /// How scores that don't convert exactly between AniList and MAL are rounded
+    #[serde(default)]
+    pub score_rounding: Rounding,
+}
+
+/// Where the AniList and MAL APIs are. Only worth changing to point at a local server for testing.
+/// Can be set in the `[api]` section of the config file or with the `LIST_BACKUP_*_URL` environment variables
+#[derive(Deserialize, Debug, Serialize, Clone)]
+#[serde(default)]
+pub struct ApiUrls {
+    pub anilist_graphql: String,
+    /// `/token` and `/authorize` are under this
+    pub anilist_oauth: String,
+    pub mal_api: String,
+    /// `/token` and `/authorize` are under this
+    pub mal_oauth: String,
+}
+
+impl Default for ApiUrls {
+    fn default() -> ApiUrls {
+        ApiUrls {
+            anilist_graphql: "https://graphql.anilist.co".to_string(),
+            anilist_oauth: "https://anilist.co/api/v2/oauth".to_string(),
+            mal_api: "https://api.myanimelist.net/v2".to_string(),
+            mal_oauth: "https://myanimelist.net/v1/oauth2".to_string(),
+        }
+    }
+}
+
+impl ApiUrls {
+    /// The URLs from the config file, with the environment variables taking priority
+    pub fn new(config: &TomlConfig) -> ApiUrls {
+        let mut urls = config.api.clone().unwrap_or_default();
+        let overrides = [
+            ("LIST_BACKUP_ANILIST_GRAPHQL_URL", &mut urls.anilist_graphql),
+            ("LIST_BACKUP_ANILIST_OAUTH_URL", &mut urls.anilist_oauth),
+            ("LIST_BACKUP_MAL_API_URL", &mut urls.mal_api),
+            ("LIST_BACKUP_MAL_OAUTH_URL", &mut urls.mal_oauth),
+        ];
+        for (name, url) in IntoIterator::into_iter(overrides) {
+            if let Ok(value) = env::var(name) {
+                *url = value;
+            }
+        }
+        urls
+    }
 }
 
 #[derive(Deserialize, Debug, Serialize)]
 pub struct AnilistConfig {
     pub token_type: String,
     pub expires_in: u32,
+    /// Unix timestamp (seconds) of when `access_token` expires.
+    /// Configs written before this existed get 0, so they're refreshed on the next run
+    #[serde(default)]
+    pub expires_at: u64,
     pub access_token: String,
     pub refresh_token: String,
     pub code: String,
     pub user_id: u32,
     pub user_name: String,
+    /// Fetched again every sync, in case it's been changed on AniList
+    pub score_format: Option<ScoreFormat>,
 }
 
 #[derive(Deserialize, Debug, Serialize)]
 pub struct MALConfig {
     pub token_type: String,
     pub expires_in: u32,
+    #[serde(default)]
+    pub expires_at: u64,
     pub access_token: String,
     pub refresh_token: String,
     pub code: String,
@@ -40,6 +132,7 @@ impl MALConfig {
         MALConfig {
             token_type: res.token_type,
             expires_in: res.expires_in,
+            expires_at: expires_at(res.expires_in),
             access_token: res.access_token,
             refresh_token: res.refresh_token,
             code: code.to_string(),
@@ -56,95 +149,196 @@ struct Response {
     refresh_token: String,
 }
 
-fn write_anilist_config(config: AnilistConfig) {
-    let mut file_path = home::home_dir().unwrap();
-    file_path.push(".config");
-    file_path.push("list-backup");
-    file_path.push("config");
-    file_path.set_extension("toml");
+/// How long before a token expires that it gets refreshed
+const REFRESH_MARGIN: u64 = 60 * 60 * 24;
 
-    let file = fs::OpenOptions::new()
-        .read(true)
-        .write(true)
-        .append(true)
-        .create(true)
-        .open(&file_path);
-
-    match file {
-        Err(error) => match error.kind() {
-            ErrorKind::NotFound => {
-                println!("The directory doesn't exist");
-                create_parent_dir(file_path);
-                write_anilist_config(config)
-            }
-            ErrorKind::PermissionDenied => {
-                panic!("You don't have the correct permission to open or read this file");
-            }
-            other_error => {
-                panic!("Unhandled error: {:?}", other_error);
-            }
-        },
-        Ok(file) => {
-            println!("Opened the file");
-            let toml_config = TomlConfig {
-                anilist: Some(config),
-                myanimelist: None,
-            };
-            let toml = toml::to_string(&toml_config).unwrap();
-            write!(&file, "{}", toml).unwrap();
-
-            println!("Completed writing to config file");
-        }
+fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap()
+        .as_secs()
+}
+
+fn expires_at(expires_in: u32) -> u64 {
+    now() + expires_in as u64
+}
+
+fn needs_refresh(expires_at: u64) -> bool {
+    expires_at <= now() + REFRESH_MARGIN
+}
+
+/// Refreshes the AniList token if it's close to expiring and saves the new one to the config file.
+/// Returns false if it needed refreshing but couldn't be, in which case the user has to authenticate again
+pub async fn ensure_anilist_token(config: &mut TomlConfig) -> Result<bool> {
+    match &config.anilist {
+        Some(anilist) if !needs_refresh(anilist.expires_at) => Ok(true),
+        Some(_) => refresh_anilist_token(config).await,
+        None => Ok(false),
     }
 }
 
-fn create_parent_dir(path: std::path::PathBuf) {
-    let path = path.parent().unwrap();
-    let created_dir = fs::create_dir_all(path);
-
-    // will error if it already exists
-    if let Err(error) = created_dir {
-        match error.kind() {
-            ErrorKind::AlreadyExists => {
-                println!("Dir already exists");
-                return;
-            }
-            other_error => {
-                panic!("Unhandled error: {:?}", other_error);
-            }
-        };
-    } else {
-        println!("Created the directory");
-        return;
+/// Makes sure the saved AniList login works. The token is refreshed if it's close to expiring,
+/// or if AniList rejects it anyway, e.g. because it was revoked. Fetching the score format is
+/// what checks it. Returns false if the user has to authenticate again
+pub async fn check_anilist_login(config: &mut TomlConfig) -> Result<bool> {
+    if !ensure_anilist_token(config).await? {
+        return Ok(false);
     }
+    match refresh_score_format(config).await {
+        Err(Error::AuthExpired(_)) => eprintln!("AniList didn't accept the saved token"),
+        result => return result.map(|_| true),
+    }
+    if !refresh_anilist_token(config).await? {
+        return Ok(false);
+    }
+    refresh_score_format(config).await?;
+    Ok(true)
 }
 
-// haven't actually tested this yet
-async fn _refresh_token(ref_token: &str) {
+/// Swaps the refresh token for a new AniList token and saves it to the config file.
+/// Returns false if there's no login or the refresh token was rejected
+async fn refresh_anilist_token(config: &mut TomlConfig) -> Result<bool> {
+    let urls = ApiUrls::new(config);
+    let anilist = match config.anilist.as_mut() {
+        Some(anilist) => anilist,
+        None => return Ok(false),
+    };
+
+    let secret = env_var("ANILIST_SECRET")?;
     let mut map = HashMap::new();
     map.insert("grant_type", "refresh_token");
-    map.insert("refresh_token", ref_token);
+    map.insert("client_id", "6593");
+    map.insert("client_secret", &secret);
+    map.insert("refresh_token", &anilist.refresh_token);
 
     let client = reqwest::Client::new();
     let res = client
-        .post("https://anilist.co/api/v2/oauth/token") // might be a dif url
+        .post(format!("{}/token", urls.anilist_oauth))
         .header("Content-Type", "application/json")
         .header("Accept", "application/json")
         .json(&map)
         .send()
-        .await
-        .unwrap()
-        .text()
-        .await;
+        .await?;
+
+    match parse_token_response(res).await? {
+        Some(response) => {
+            anilist.token_type = response.token_type;
+            anilist.expires_in = response.expires_in;
+            anilist.expires_at = expires_at(response.expires_in);
+            anilist.access_token = response.access_token;
+            anilist.refresh_token = response.refresh_token;
+            save_config(config)?;
+            eprintln!("Refreshed the AniList token");
+            Ok(true)
+        }
+        None => Ok(false),
+    }
+}
 
-    if let Ok(res) = res {
-        let response: Response = serde_json::from_str(&res).unwrap();
-        println!("{:?}", response);
+/// Fetches the user's AniList score format, saving it to the config file if it's changed.
+/// Needs a valid AniList token
+pub async fn refresh_score_format(config: &mut TomlConfig) -> Result<()> {
+    let urls = ApiUrls::new(config);
+    let anilist = match config.anilist.as_mut() {
+        Some(anilist) => anilist,
+        None => return Ok(()),
+    };
+    let score_format = anilist_queries::get_score_format(&urls, &anilist.access_token).await?;
+    if anilist.score_format != Some(score_format) {
+        anilist.score_format = Some(score_format);
+        save_config(config)?;
     }
+    Ok(())
 }
 
-pub async fn get_anilist_token(code: &str) {
-    let secret = env::var("ANILIST_SECRET").unwrap();
+/// Same as `ensure_anilist_token` but for MAL
+pub async fn ensure_mal_token(config: &mut TomlConfig) -> Result<bool> {
+    match &config.myanimelist {
+        Some(mal) if !needs_refresh(mal.expires_at) => Ok(true),
+        Some(_) => refresh_mal_token(config).await,
+        None => Ok(false),
+    }
+}
+
+/// Same as `check_anilist_login` but for MAL, which checks the token by fetching the user
+pub async fn check_mal_login(config: &mut TomlConfig) -> Result<bool> {
+    if !ensure_mal_token(config).await? {
+        return Ok(false);
+    }
+    let urls = ApiUrls::new(config);
+    let mal = config.myanimelist.as_ref().unwrap();
+    match mal_queries::get_user(mal, &urls).await {
+        Err(Error::AuthExpired(_)) => eprintln!("MyAnimeList didn't accept the saved token"),
+        result => return result.map(|_| true),
+    }
+    if !refresh_mal_token(config).await? {
+        return Ok(false);
+    }
+    let mal = config.myanimelist.as_ref().unwrap();
+    mal_queries::get_user(mal, &urls).await?;
+    Ok(true)
+}
+
+/// Same as `refresh_anilist_token` but for MAL
+async fn refresh_mal_token(config: &mut TomlConfig) -> Result<bool> {
+    let urls = ApiUrls::new(config);
+    let mal = match config.myanimelist.as_mut() {
+        Some(mal) => mal,
+        None => return Ok(false),
+    };
+
+    let id = env_var("MAL_CLIENT_ID")?;
+    let secret = env_var("MAL_SECRET")?;
+    let body = format!(
+        "client_id={}&client_secret={}&grant_type=refresh_token&refresh_token={}",
+        id, secret, mal.refresh_token
+    );
+
+    let client = reqwest::Client::new();
+    let res = client
+        .post(format!("{}/token", urls.mal_oauth))
+        .header("Content-Type", "application/x-www-form-urlencoded")
+        .body(body)
+        .send()
+        .await?;
+
+    match parse_token_response(res).await? {
+        Some(response) => {
+            mal.token_type = response.token_type;
+            mal.expires_in = response.expires_in;
+            mal.expires_at = expires_at(response.expires_in);
+            mal.access_token = response.access_token;
+            mal.refresh_token = response.refresh_token;
+            save_config(config)?;
+            eprintln!("Refreshed the MyAnimeList token");
+            Ok(true)
+        }
+        None => Ok(false),
+    }
+}
+
+/// `None` if the refresh token was rejected
+async fn parse_token_response(res: reqwest::Response) -> Result<Option<Response>> {
+    if !res.status().is_success() {
+        eprintln!("Refreshing the token failed with status {}", res.status());
+        return Ok(None);
+    }
+    let text = res.text().await?;
+    Ok(serde_json::from_str(&text).ok())
+}
+
+/// Reads an environment variable, which can also be set in `.env`
+pub fn env_var(name: &str) -> Result<String> {
+    env::var(name).map_err(|_| {
+        Error::Config(format!(
+            "{} isn't set. Add it to the environment or a .env file",
+            name
+        ))
+    })
+}
+
+pub async fn get_anilist_token(urls: &ApiUrls, code: &str) -> Result<()> {
+    let secret = env_var("ANILIST_SECRET")?;
 
     let mut map = HashMap::new();
     map.insert("grant_type", "authorization_code");
@@ -155,35 +349,35 @@ pub async fn get_anilist_token(code: &str) {
 
     let client = reqwest::Client::new();
     let res = client
-        .post("https://anilist.co/api/v2/oauth/token")
+        .post(format!("{}/token", urls.anilist_oauth))
         .header("Content-Type", "application/json")
         .header("Accept", "application/json")
         .json(&map)
         .send()
-        .await
-        .unwrap()
+        .await?
         .text()
-        .await;
-
-    if let Ok(res) = res {
-        let response: Response = serde_json::from_str(&res).unwrap();
-        let user_data = anilist_queries::get_user_id(response.access_token.clone()).await;
-        let config = AnilistConfig {
-            token_type: response.token_type,
-            expires_in: response.expires_in,
-            access_token: response.access_token,
-            refresh_token: response.refresh_token,
-            code: code.to_string(),
-            user_id: user_data.id,
-            user_name: user_data.name,
-        };
-        write_anilist_config(config);
-    }
+        .await?;
+
+    let response: Response = parse_login_response("AniList", &res)?;
+    let user_data = anilist_queries::get_user_id(urls, response.access_token.clone()).await?;
+    let score_format = anilist_queries::get_score_format(urls, &response.access_token).await?;
+    let config = AnilistConfig {
+        token_type: response.token_type,
+        expires_in: response.expires_in,
+        expires_at: expires_at(response.expires_in),
+        access_token: response.access_token,
+        refresh_token: response.refresh_token,
+        code: code.to_string(),
+        user_id: user_data.id,
+        user_name: user_data.name,
+        score_format: Some(score_format),
+    };
+    write_anilist_config(config)
 }
 
-pub async fn get_mal_token(code: &str, pkce: &str) {
-    let id = env::var("MAL_CLIENT_ID").unwrap();
-    let secret = env::var("MAL_SECRET").unwrap();
+pub async fn get_mal_token(urls: &ApiUrls, code: &str, pkce: &str) -> Result<()> {
+    let id = env_var("MAL_CLIENT_ID")?;
+    let secret = env_var("MAL_SECRET")?;
 
     // refresh token works the same, but `grant_type` is different
     let body = format!(
@@ -193,59 +387,104 @@ pub async fn get_mal_token(code: &str, pkce: &str) {
 
     let client = reqwest::Client::new();
     let res = client
-        .post("https://myanimelist.net/v1/oauth2/token")
+        .post(format!("{}/token", urls.mal_oauth))
         .header("Content-Type", "application/x-www-form-urlencoded")
         .body(body)
         .send()
-        .await
-        .unwrap()
+        .await?
         .text()
-        .await
-        .unwrap();
+        .await?;
 
-    let response: Response = serde_json::from_str(&res).unwrap();
+    let response: Response = parse_login_response("MyAnimeList", &res)?;
     let config = MALConfig::new(response, code, pkce);
-    write_mal_config(config);
+    write_mal_config(config)
+}
+
+/// Anything other than a token means the code was rejected, and the body says why
+fn parse_login_response(service: &'static str, res: &str) -> Result<Response> {
+    serde_json::from_str(res).map_err(|_| Error::Api(service, res.to_string()))
+}
+
+/// `~/.config/list-backup`, where the config file and anything else the program keeps goes
+pub fn config_dir() -> Result<PathBuf> {
+    let mut dir = home::home_dir()
+        .ok_or_else(|| Error::Config("Couldn't find your home directory".to_string()))?;
+    dir.push(".config");
+    dir.push("list-backup");
+    Ok(dir)
 }
 
-fn write_mal_config(config: MALConfig) {
-    let mut file_path = home::home_dir().unwrap();
-    file_path.push(".config");
-    file_path.push("list-backup");
+pub fn config_path() -> Result<PathBuf> {
+    let mut file_path = config_dir()?;
     file_path.push("config");
     file_path.set_extension("toml");
+    Ok(file_path)
+}
 
-    let file = fs::OpenOptions::new()
-        .read(true)
-        .write(true)
-        .append(true)
-        .create(true)
-        .open(&file_path);
-
-    match file {
-        Err(error) => match error.kind() {
-            ErrorKind::NotFound => {
-                println!("The directory doesn't exist");
-                create_parent_dir(file_path);
-                write_mal_config(config)
-            }
-            ErrorKind::PermissionDenied => {
-                panic!("You don't have the correct permission to open or read this file");
-            }
-            other_error => {
-                panic!("Unhandled error: {:?}", other_error);
-            }
-        },
-        Ok(file) => {
-            println!("Opened the file");
-            let toml_config = TomlConfig {
-                anilist: None,
-                myanimelist: Some(config),
-            };
-            let toml = toml::to_string(&toml_config).unwrap();
-            write!(&file, "{}", toml).unwrap();
-
-            println!("Completed writing to config file");
-        }
+/// Reads the config file, or an empty config if there isn't one yet
+pub fn load_config() -> Result<TomlConfig> {
+    let file_path = config_path()?;
+    match fs::read_to_string(&file_path) {
+        Ok(file_string) => toml::from_str(&file_string).map_err(|error| {
+            Error::Config(format!(
+                "Couldn't parse the config file at {}: {}",
+                file_path.display(),
+                error
+            ))
+        }),
+        Err(error) if error.kind() == ErrorKind::NotFound => Ok(TomlConfig::default()),
+        Err(error) => Err(Error::Io(file_path, error)),
     }
 }
+
+/// Replaces the config file with `config`.
+/// It's written to a temporary file first and then renamed over the old one,
+/// so the config is never left half written. The file is only readable by the user
+pub fn save_config(config: &TomlConfig) -> Result<()> {
+    let file_path = config_path()?;
+    create_parent_dir(&file_path)?;
+
+    // everything in the config can be represented in TOML
+    let toml = toml::to_string(config).unwrap();
+    let temp_path = file_path.with_extension("toml.tmp");
+
+    let mut options = fs::OpenOptions::new();
+    options.write(true).create(true).truncate(true);
+    #[cfg(unix)]
+    options.mode(0o600);
+
+    let mut file = options.open(&temp_path).map_err(Error::io(&temp_path))?;
+    // the mode only applies to new files, so make sure a leftover temp file is restricted too
+    #[cfg(unix)]
+    file.set_permissions(fs::Permissions::from_mode(0o600))
+        .map_err(Error::io(&temp_path))?;
+    file.write_all(toml.as_bytes())
+        .and_then(|_| file.sync_all())
+        .map_err(Error::io(&temp_path))?;
+
+    fs::rename(&temp_path, &file_path).map_err(Error::io(&file_path))
+}
+
+/// Saves the AniList section, leaving the rest of the config alone
+fn write_anilist_config(config: AnilistConfig) -> Result<()> {
+    let mut toml_config = load_config()?;
+    toml_config.anilist = Some(config);
+    save_config(&toml_config)?;
+    println!("Completed writing to config file");
+    Ok(())
+}
+
+/// Saves the MyAnimeList section, leaving the rest of the config alone
+fn write_mal_config(config: MALConfig) -> Result<()> {
+    let mut toml_config = load_config()?;
+    toml_config.myanimelist = Some(config);
+    save_config(&toml_config)?;
+    println!("Completed writing to config file");
+    Ok(())
+}
+
+fn create_parent_dir(path: &Path) -> Result<()> {
+    let path = path.parent().unwrap();
+    // doesn't error if it already exists
+    fs::create_dir_all(path).map_err(Error::io(path))
+}
@@ -0,0 +1,167 @@
+use std::collections::HashMap;
+
+use serde::Serialize;
+
+use super::anilist_queries::MediaListStatus;
+use super::save_to_file::{BackupToml, EntrySection};
+
+/// What changed between two backups, keyed on AniList id
+#[derive(Serialize, Debug)]
+pub struct BackupDiff {
+    pub added: Vec<EntrySummary>,
+    pub removed: Vec<EntrySummary>,
+    pub changed: Vec<EntryChange>,
+}
+
+#[derive(Serialize, Debug)]
+pub struct EntrySummary {
+    pub id: u32,
+    pub title: String,
+    pub status: MediaListStatus,
+}
+
+/// Only the fields that changed are set
+#[derive(Serialize, Debug)]
+pub struct EntryChange {
+    pub id: u32,
+    pub title: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub status: Option<Transition<MediaListStatus>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub score: Option<Transition<f32>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub progress: Option<ProgressChange>,
+}
+
+#[derive(Serialize, Debug)]
+pub struct Transition<T> {
+    pub from: T,
+    pub to: T,
+}
+
+#[derive(Serialize, Debug)]
+pub struct ProgressChange {
+    pub from: u32,
+    pub to: u32,
+    pub delta: i64,
+}
+
+impl BackupDiff {
+    pub fn is_empty(&self) -> bool {
+        self.added.is_empty() && self.removed.is_empty() && self.changed.is_empty()
+    }
+}
+
+pub fn diff_backups(old: &BackupToml, new: &BackupToml) -> BackupDiff {
+    let old_entries: HashMap<u32, &EntrySection> = old.entries().map(|e| (e.id, e)).collect();
+    let new_entries: HashMap<u32, &EntrySection> = new.entries().map(|e| (e.id, e)).collect();
+
+    let mut added = Vec::new();
+    let mut changed = Vec::new();
+    // going through the backup in order keeps the output in the same order as the file
+    for new_entry in new.entries() {
+        match old_entries.get(&new_entry.id) {
+            None => added.push(summary(new_entry)),
+            Some(old_entry) => {
+                if let Some(change) = compare(old_entry, new_entry) {
+                    changed.push(change);
+                }
+            }
+        }
+    }
+    let removed = old
+        .entries()
+        .filter(|old_entry| !new_entries.contains_key(&old_entry.id))
+        .map(summary)
+        .collect();
+
+    BackupDiff {
+        added,
+        removed,
+        changed,
+    }
+}
+
+fn summary(entry: &EntrySection) -> EntrySummary {
+    EntrySummary {
+        id: entry.id,
+        title: entry.title.clone(),
+        status: entry.status,
+    }
+}
+
+fn compare(old: &EntrySection, new: &EntrySection) -> Option<EntryChange> {
+    let status = if old.status != new.status {
+        Some(Transition {
+            from: old.status,
+            to: new.status,
+        })
+    } else {
+        None
+    };
+    let score = if old.score != new.score {
+        Some(Transition {
+            from: old.score,
+            to: new.score,
+        })
+    } else {
+        None
+    };
+    let progress = if old.progress != new.progress {
+        Some(ProgressChange {
+            from: old.progress,
+            to: new.progress,
+            delta: new.progress as i64 - old.progress as i64,
+        })
+    } else {
+        None
+    };
+
+    if status.is_none() && score.is_none() && progress.is_none() {
+        return None;
+    }
+    Some(EntryChange {
+        id: new.id,
+        title: new.title.clone(),
+        status,
+        score,
+        progress,
+    })
+}
+
+pub fn print_diff(diff: &BackupDiff) {
+    if diff.is_empty() {
+        println!("No changes");
+        return;
+    }
+    if !diff.added.is_empty() {
+        println!("Added:");
+        for entry in diff.added.iter() {
+            println!("  + {} ({:?})", entry.title, entry.status);
+        }
+    }
+    if !diff.removed.is_empty() {
+        println!("Removed:");
+        for entry in diff.removed.iter() {
+            println!("  - {} ({:?})", entry.title, entry.status);
+        }
+    }
+    if !diff.changed.is_empty() {
+        println!("Changed:");
+        for change in diff.changed.iter() {
+            println!("  ~ {}", change.title);
+            if let Some(status) = &change.status {
+                println!("      Status: {:?} -> {:?}", status.from, status.to);
+            }
+            if let Some(score) = &change.score {
+                println!("      Score: {} -> {}", score.from, score.to);
+            }
+            if let Some(progress) = &change.progress {
+                println!(
+                    "      Progress: {} -> {} ({:+})",
+                    progress.from, progress.to, progress.delta
+                );
+            }
+        }
+    }
+}
@@ -0,0 +1,74 @@
+use std::fmt;
+use std::io;
+use std::path::PathBuf;
+
+#[derive(Debug)]
+pub enum Error {
+    /// Something wrong with the arguments or a file that was passed in
+    Input(String),
+    /// The config file or an environment variable is missing or invalid
+    Config(String),
+    /// Reading or writing a file failed
+    Io(PathBuf, io::Error),
+    /// The request never got a response
+    Network(reqwest::Error),
+    /// There's no working token for the service and logging in again needs someone there
+    /// to do it. Holds the name of the service
+    AuthExpired(&'static str),
+    /// The service responded with an error, or with something that couldn't be parsed
+    Api(&'static str, String),
+    /// Some of the changes in a plan couldn't be applied. Holds how many
+    UpdatesFailed(usize),
+}
+
+pub type Result<T> = std::result::Result<T, Error>;
+
+impl Error {
+    /// The process exit code, so scripts can tell what went wrong. 1 is left for clap's usage errors
+    pub fn exit_code(&self) -> i32 {
+        match self {
+            Error::Input(_) => 2,
+            Error::Config(_) => 3,
+            Error::Io(_, _) => 4,
+            Error::Network(_) => 5,
+            Error::AuthExpired(_) => 6,
+            Error::Api(_, _) => 7,
+            Error::UpdatesFailed(_) => 8,
+        }
+    }
+
+    pub fn io(path: impl Into<PathBuf>) -> impl FnOnce(io::Error) -> Error {
+        let path = path.into();
+        move |error| Error::Io(path, error)
+    }
+
+    /// For responses that don't have the shape that was expected
+    pub fn unexpected_response(service: &'static str, error: serde_json::Error) -> Error {
+        Error::Api(service, format!("unexpected response ({})", error))
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Error::Input(message) | Error::Config(message) => write!(f, "{}", message),
+            Error::Io(path, error) => write!(f, "Couldn't access {}: {}", path.display(), error),
+            Error::Network(error) => write!(f, "Couldn't connect: {}", error),
+            Error::AuthExpired(service) => write!(
+                f,
+                "Not logged in to {}, or the saved login expired and couldn't be refreshed. Run the command in a terminal without --yes or --json to log in",
+                service
+            ),
+            Error::Api(service, message) => write!(f, "{} returned an error: {}", service, message),
+            Error::UpdatesFailed(count) => write!(f, "{} of the changes couldn't be applied", count),
+        }
+    }
+}
+
+impl std::error::Error for Error {}
+
+impl From<reqwest::Error> for Error {
+    fn from(error: reqwest::Error) -> Error {
+        Error::Network(error)
+    }
+}
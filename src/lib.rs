@@ -0,0 +1,13 @@
+pub mod anilist_queries;
+pub mod config;
+pub mod diff;
+pub mod error;
+pub mod mal_queries;
+pub mod mappings;
+pub mod provider;
+pub mod resolver;
+pub mod restore;
+pub mod save_to_file;
+pub mod scores;
+pub mod snapshots;
+pub mod sync;
@@ -1,21 +1,23 @@
-use std::env;
-use std::fs;
-use std::io::prelude::*;
-use std::io::{self, ErrorKind};
+use std::io::{self, IsTerminal};
+use std::path::{Path, PathBuf};
+use std::process;
+use std::sync::{Arc, Mutex};
 
-use clap::{App, Arg, SubCommand};
+use clap::{App, Arg, ArgMatches, SubCommand};
 use dotenv::dotenv;
-use home;
 use rand::{thread_rng, Rng};
 use rocket::Config as RocketConfig;
 use rocket::Shutdown;
 
-mod config;
-use config::TomlConfig;
-mod anilist_queries;
-use anilist_queries::MediaType;
-mod mal_queries;
-mod save_to_file;
+use list_backup::anilist_queries::{self, AnilistClient, MediaType};
+use list_backup::config::{ApiUrls, TomlConfig};
+use list_backup::error::{Error, Result};
+use list_backup::mal_queries::{self, MalClient};
+use list_backup::mappings::{self, Mapping};
+use list_backup::provider::{ListProvider, Service, User};
+use list_backup::save_to_file::{self, Format, ListSection, Output};
+use list_backup::sync::{self, Direction};
+use list_backup::{config, diff, resolver, restore, snapshots};
 
 pub struct PKCE {
     code_challenge: String,
@@ -25,38 +27,282 @@ const CHARS: &str = "ABCDEFGHIJKLMNOPQRSTUVWXYZ\
     abcdefghijklmnopqrstuvwxyz\
     0123456789-.~_";
 
+/// Where the callbacks leave an error, since they can't return it to `start_rocket` themselves
+type LoginError = Arc<Mutex<Option<Error>>>;
+
 #[rocket::get("/anilist?<code>")]
-async fn anilist(code: &str, shutdown: Shutdown) -> &'static str {
+async fn anilist(
+    code: &str,
+    urls: &rocket::State<ApiUrls>,
+    login_error: &rocket::State<LoginError>,
+    shutdown: Shutdown,
+) -> &'static str {
     shutdown.notify();
 
-    config::get_anilist_token(code).await;
-    "You may close this page now and return to the terminal"
+    login_finished(config::get_anilist_token(urls, code).await, login_error)
 }
 
 #[rocket::get("/myanimelist?<code>")]
-async fn myanimelist(code: &str, pkce: &rocket::State<PKCE>, shutdown: Shutdown) -> &'static str {
+async fn myanimelist(
+    code: &str,
+    pkce: &rocket::State<PKCE>,
+    urls: &rocket::State<ApiUrls>,
+    login_error: &rocket::State<LoginError>,
+    shutdown: Shutdown,
+) -> &'static str {
     shutdown.notify();
 
-    config::get_mal_token(code, &pkce.code_challenge).await;
-    "You may close this page now and return to the terminal"
+    login_finished(
+        config::get_mal_token(urls, code, &pkce.code_challenge).await,
+        login_error,
+    )
+}
+
+fn login_finished(result: Result<()>, login_error: &LoginError) -> &'static str {
+    match result {
+        Ok(()) => "You may close this page now and return to the terminal",
+        Err(error) => {
+            *login_error.lock().unwrap() = Some(error);
+            "Logging in failed. The terminal has the details"
+        }
+    }
 }
 
-async fn start_rocket(pkce: PKCE) {
+async fn start_rocket(pkce: PKCE, urls: ApiUrls) -> Result<()> {
     let rocket_config = RocketConfig {
         port: 5000,
         log_level: rocket::config::LogLevel::Off,
         ..RocketConfig::debug_default()
     };
 
+    let login_error = LoginError::default();
     let server = rocket::custom(&rocket_config)
         .mount("/", rocket::routes![anilist, myanimelist])
         .manage(pkce)
+        .manage(urls)
+        .manage(login_error.clone())
         .launch()
         .await;
 
     if let Err(error) = server {
-        panic!("There was an error: {}", error);
+        return Err(Error::Config(format!(
+            "Couldn't start the login server on port 5000: {}",
+            error
+        )));
+    }
+    let error = login_error.lock().unwrap().take();
+    match error {
+        Some(error) => Err(error),
+        None => Ok(()),
+    }
+}
+
+async fn run_sync(matches: &ArgMatches<'_>, direction: Direction) -> Result<()> {
+    let list_type = parse_list_type(matches.value_of("list type"))?;
+    let page_size = match matches.value_of("page size") {
+        Some(page_size) => match page_size.parse::<u32>() {
+            Ok(page_size) if (1..=mal_queries::MAX_PAGE_SIZE).contains(&page_size) => page_size,
+            _ => {
+                return Err(Error::Input(
+                    "The value for 'page size' needs to be a number between 1 and 1000".to_string(),
+                ))
+            }
+        },
+        None => mal_queries::MAX_PAGE_SIZE,
+    };
+
+    let yes = matches.is_present("yes");
+    let dry_run = matches.is_present("dry run");
+    let plan_file = matches.value_of("plan file");
+
+    // logging in again needs someone at a terminal, and scripts pass --yes or --json
+    let can_log_in = !yes && !matches.is_present("json") && io::stdin().is_terminal();
+
+    let mut config = config::load_config()?;
+    // refresh both tokens up front, only asking to authenticate again if that fails.
+    // Logging in to AniList also gets the score format the user has now
+    login(&mut config, Service::Mal, can_log_in).await?;
+    login(&mut config, Service::Anilist, can_log_in).await?;
+    let urls = ApiUrls::new(&config);
+    let scores = config.score_conversion();
+    let mal = MalClient {
+        config: config.myanimelist.as_ref().unwrap(),
+        urls: &urls,
+        page_size,
+        scores,
+    };
+    let anilist = AnilistClient {
+        config: config.anilist.as_ref().unwrap(),
+        urls: &urls,
+    };
+
+    let plan = match plan_file {
+        // applying a plan that was saved with --dry-run
+        Some(plan_file) if !dry_run => {
+            let plan = sync::read_plan(plan_file)?;
+            if plan.list_type != list_type {
+                return Err(Error::Input(format!(
+                    "The plan in {} is for a {:?} list, not {:?}",
+                    plan_file, plan.list_type, list_type
+                )));
+            }
+            plan
+        }
+        _ => {
+            // just going to get the current list from mal before trying to update anything
+            let mal_list = mal.fetch_list(list_type).await?;
+            let mut anilist_list = anilist.fetch_list(list_type).await?;
+            // the user's own mappings win over what AniList links entries to
+            let mappings = mappings::load_mappings()?;
+            mappings.apply(&mut anilist_list);
+            // only ask about entries AniList hasn't linked to MAL when there's someone to answer
+            let interactive = !yes && !matches.is_present("json");
+            resolver::resolve(&mal, &mappings, &mut anilist_list, list_type, interactive).await?;
+            // compare them both and work out which one is behind
+            sync::build_plan(
+                &anilist,
+                &mappings,
+                &anilist_list,
+                &mal_list,
+                list_type,
+                direction,
+                &scores,
+            )
+            .await?
+        }
+    };
+
+    if dry_run {
+        if let Some(plan_file) = plan_file {
+            sync::write_plan(&plan, plan_file)?;
+            eprintln!("Saved the plan to {}", plan_file);
+        }
+        if matches.is_present("json") {
+            println!("{}", serde_json::to_string_pretty(&plan).unwrap());
+        } else {
+            sync::print_plan(&plan);
+        }
+        return Ok(());
     }
+
+    sync::apply_plan(&[&anilist, &mal], &plan, yes).await
+}
+
+/// The options shared by `update` and `sync`
+fn sync_args<'a, 'b>() -> Vec<Arg<'a, 'b>> {
+    vec![
+        Arg::with_name("page size")
+            .long("page-size")
+            .takes_value(true)
+            .help("How many MAL entries to request per page. Between 1 and 1000"),
+        Arg::with_name("yes")
+            .long("yes")
+            .short("y")
+            .help("Apply every change without asking"),
+        Arg::with_name("dry run")
+            .long("dry-run")
+            .help("Print the changes that would be made without making them"),
+        Arg::with_name("json")
+            .long("json")
+            .requires("dry run")
+            .help("Print the dry run plan as JSON"),
+        Arg::with_name("plan file")
+            .long("plan-file")
+            .takes_value(true)
+            .help("With --dry-run, save the plan to this file. Without it, apply the saved plan"),
+    ]
+}
+
+/// Makes sure there's a working token for `service`, refreshing it if it's close to expiring
+/// or the service rejects it. If it can't be refreshed, logging in again needs a browser, so that's only done when
+/// `can_log_in`. Otherwise it's an `Error::AuthExpired`, rather than waiting for a login that
+/// never comes. After logging in, `config` has the new token and the command carries on
+async fn login(config: &mut TomlConfig, service: Service, can_log_in: bool) -> Result<()> {
+    let logged_in = match service {
+        Service::Anilist => config::check_anilist_login(config).await?,
+        Service::Mal => config::check_mal_login(config).await?,
+    };
+    if logged_in {
+        return Ok(());
+    }
+    if !can_log_in {
+        return Err(Error::AuthExpired(service.name()));
+    }
+
+    let pkce = PKCE {
+        code_challenge: create_code_challenge(),
+    };
+    let urls = ApiUrls::new(config);
+    match service {
+        Service::Anilist => authenticate_anilist(pkce, urls).await?,
+        Service::Mal => authenticate_mal(pkce, urls).await?,
+    }
+    // logging in saved the new token to the config file
+    *config = config::load_config()?;
+    Ok(())
+}
+
+async fn authenticate_anilist(pkce: PKCE, urls: ApiUrls) -> Result<()> {
+    println!("Go here to authenticate: {}/authorize?client_id=6593&redirect_uri=http://localhost:5000/anilist&response_type=code", urls.anilist_oauth);
+    start_rocket(pkce, urls).await
+}
+
+async fn authenticate_mal(pkce: PKCE, urls: ApiUrls) -> Result<()> {
+    let mut auth_link = String::new();
+    auth_link.push_str(&urls.mal_oauth);
+    auth_link.push_str("/authorize?response_type=code&client_id=");
+    auth_link.push_str(&config::env_var("MAL_CLIENT_ID")?);
+    auth_link.push_str("&code_challenge=");
+    auth_link.push_str(&pkce.code_challenge);
+    println!("Go here to authenticate: {}", auth_link);
+
+    start_rocket(pkce, urls).await
+}
+
+fn parse_list_type(list_type: Option<&str>) -> Result<MediaType> {
+    let list_type = match list_type {
+        Some(list_type) => list_type,
+        None => return Err(Error::Input("Didn't get list type".to_string())),
+    };
+    match list_type.to_uppercase().as_str() {
+        "ANIME" => Ok(MediaType::ANIME),
+        "MANGA" => Ok(MediaType::MANGA),
+        _ => Err(Error::Input(
+            "The value for 'list type' needs to be either 'anime' or 'manga', case insensitive"
+                .to_string(),
+        )),
+    }
+}
+
+/// The snapshot directory, taking the config file into account
+fn backup_dir(config: &config::TomlConfig) -> Result<PathBuf> {
+    let configured = config
+        .backup
+        .as_ref()
+        .and_then(|backup| backup.directory.as_deref());
+    snapshots::snapshot_dir(configured)
+}
+
+/// Keeping 0 backups would delete the one that was just written, so counts start at 1
+fn parse_count(value: Option<&str>, name: &str) -> Result<Option<usize>> {
+    value
+        .map(|value| match value.parse::<usize>() {
+            Ok(count) if count > 0 => Ok(count),
+            _ => Err(Error::Input(format!(
+                "The value for '{}' needs to be a whole number above 0",
+                name
+            ))),
+        })
+        .transpose()
+}
+
+fn parse_id(value: Option<&str>, name: &str) -> Result<u32> {
+    value.unwrap().parse::<u32>().map_err(|_| {
+        Error::Input(format!(
+            "The value for '{}' needs to be a whole number",
+            name
+        ))
+    })
 }
 
 fn create_code_challenge() -> String {
@@ -76,7 +322,8 @@ fn create_code_challenge() -> String {
 
 #[rocket::main]
 async fn main() {
-    dotenv().unwrap();
+    // everything in .env can also be set in the environment
+    dotenv().ok();
 
     let matches = App::new("List Backup")
         .version("0.1.0")
@@ -88,6 +335,122 @@ async fn main() {
                     Arg::with_name("list type")
                         .help("The type of list to backup. Either 'anime' or 'manga'")
                         .required(true),
+                )
+                .arg(
+                    Arg::with_name("keep")
+                        .long("keep")
+                        .takes_value(true)
+                        .help("Only keep the newest n backups of this list"),
+                )
+                .arg(
+                    Arg::with_name("keep daily")
+                        .long("keep-daily")
+                        .takes_value(true)
+                        .help("Keep the newest backup of each of the last n days"),
+                )
+                .arg(
+                    Arg::with_name("keep weekly")
+                        .long("keep-weekly")
+                        .takes_value(true)
+                        .help("Keep the newest backup of each of the last n weeks"),
+                )
+                .arg(
+                    Arg::with_name("keep monthly")
+                        .long("keep-monthly")
+                        .takes_value(true)
+                        .help("Keep the newest backup of each of the last n months"),
+                )
+                .arg(
+                    Arg::with_name("output")
+                        .long("output")
+                        .short("o")
+                        .takes_value(true)
+                        .conflicts_with_all(&["keep", "keep daily", "keep weekly", "keep monthly"])
+                        .help("Write the backup to this file instead of a new snapshot. '-' writes to stdout"),
+                )
+                .arg(
+                    Arg::with_name("format")
+                        .long("format")
+                        .takes_value(true)
+                        .possible_values(&["toml", "json", "mal-xml", "csv"])
+                        .default_value("toml")
+                        .help("The format to write the backup in"),
+                )
+                .arg(
+                    Arg::with_name("compact")
+                        .long("compact")
+                        .help("Write JSON on a single line instead of pretty printing it"),
+                )
+                .arg(
+                    Arg::with_name("columns")
+                        .long("columns")
+                        .takes_value(true)
+                        .help("Comma separated columns to include in CSV. Defaults to all of: title, id, id_mal, format, status, score, progress, episodes, chapters"),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("backups")
+                .about("Manages the stored backups")
+                .subcommand(
+                    SubCommand::with_name("list")
+                        .about("Lists the stored backups, newest first")
+                        .arg(
+                            Arg::with_name("list type")
+                                .help("Only show 'anime' or 'manga' backups"),
+                        ),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("restore")
+                .about("Restores your Anilist or MAL list from a backup")
+                .arg(
+                    Arg::with_name("file")
+                        .help("The TOML or JSON backup to restore")
+                        .required(true),
+                )
+                .arg(
+                    Arg::with_name("target")
+                        .long("target")
+                        .takes_value(true)
+                        .possible_values(&["anilist", "mal"])
+                        .default_value("anilist")
+                        .help("Which service to restore the list to"),
+                )
+                .arg(
+                    Arg::with_name("yes")
+                        .long("yes")
+                        .short("y")
+                        .help("Apply every change without asking"),
+                )
+                .arg(
+                    Arg::with_name("dry run")
+                        .long("dry-run")
+                        .help("Print the changes that would be made without making them"),
+                )
+                .arg(
+                    Arg::with_name("json")
+                        .long("json")
+                        .requires("dry run")
+                        .help("Print the dry run plan as JSON"),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("diff")
+                .about("Shows what changed between two backups")
+                .arg(
+                    Arg::with_name("old")
+                        .help("The older TOML or JSON backup")
+                        .required(true),
+                )
+                .arg(
+                    Arg::with_name("new")
+                        .help("The newer TOML or JSON backup")
+                        .required(true),
+                )
+                .arg(
+                    Arg::with_name("json")
+                        .long("json")
+                        .help("Print the differences as JSON"),
                 ),
         )
         .subcommand(
@@ -97,271 +460,303 @@ async fn main() {
                     Arg::with_name("list type")
                         .help("The type of list to update. Either 'anime' or 'manga'")
                         .required(true),
-                ),
+                )
+                .args(&sync_args()),
+        )
+        .subcommand(
+            SubCommand::with_name("sync")
+                .about("Syncs your lists between Anilist and MAL")
+                .arg(
+                    Arg::with_name("list type")
+                        .help("The type of list to sync. Either 'anime' or 'manga'")
+                        .required(true),
+                )
+                .arg(
+                    Arg::with_name("direction")
+                        .long("direction")
+                        .takes_value(true)
+                        .possible_values(&["anilist-to-mal", "mal-to-anilist", "both"])
+                        .default_value("both")
+                        .help("Which way to sync. 'both' keeps whichever entry was updated last"),
+                )
+                .args(&sync_args()),
+        )
+        .subcommand(
+            SubCommand::with_name("mapping")
+                .about("Manages your own AniList to MAL id mappings, used instead of AniList's")
+                .subcommand(
+                    SubCommand::with_name("add")
+                        .about("Maps an AniList id to a MAL id, or blocks the pair with --block")
+                        .arg(
+                            Arg::with_name("anilist id")
+                                .help("The id of the anime or manga on AniList")
+                                .required(true),
+                        )
+                        .arg(
+                            Arg::with_name("mal id")
+                                .help("The id of the anime or manga on MAL")
+                                .required(true),
+                        )
+                        .arg(
+                            Arg::with_name("block")
+                                .long("block")
+                                .help("Never map the AniList id to this MAL id, even if AniList does"),
+                        ),
+                )
+                .subcommand(
+                    SubCommand::with_name("remove")
+                        .about("Removes the mapping and any blocks for an AniList id")
+                        .arg(
+                            Arg::with_name("anilist id")
+                                .help("The id of the anime or manga on AniList")
+                                .required(true),
+                        ),
+                )
+                .subcommand(SubCommand::with_name("list").about("Lists your mappings")),
         )
         .get_matches();
 
-    let pkce = PKCE {
-        code_challenge: create_code_challenge(),
-    };
-
-    //the config file
-    let mut file_path = home::home_dir().unwrap();
-    file_path.push(".config");
-    file_path.push("list-backup");
-    file_path.push("config");
-    file_path.set_extension("toml");
+    if let Err(error) = run(&matches).await {
+        eprintln!("Error: {}", error);
+        process::exit(error.exit_code());
+    }
+}
 
+async fn run(matches: &ArgMatches<'_>) -> Result<()> {
     match matches.subcommand() {
         ("backup", Some(backup_matches)) => {
-            if let Some(list_type) = backup_matches.value_of("list type") {
-                let list_type_uppercase = list_type.to_uppercase();
-                let list_type: MediaType;
-                if list_type_uppercase == "ANIME" {
-                    list_type = MediaType::ANIME
-                } else if list_type_uppercase == "MANGA" {
-                    list_type = MediaType::MANGA
-                } else {
-                    panic!("The value for 'list type' needs to be either 'anime' or 'manga', case insensitive")
-                }
+            let list_type = parse_list_type(backup_matches.value_of("list type"))?;
+            // checked before logging in or writing anything
+            let retention = snapshots::Retention {
+                keep_last: parse_count(backup_matches.value_of("keep"), "keep")?,
+                keep_daily: parse_count(backup_matches.value_of("keep daily"), "keep-daily")?,
+                keep_weekly: parse_count(backup_matches.value_of("keep weekly"), "keep-weekly")?,
+                keep_monthly: parse_count(backup_matches.value_of("keep monthly"), "keep-monthly")?,
+            };
 
-                let file = fs::OpenOptions::new()
-                    .read(true)
-                    .write(true)
-                    .open(file_path);
-                match file {
-                    Err(error) => match error.kind() {
-                        ErrorKind::NotFound => {
-                            println!("Go here to authenticate: https://anilist.co/api/v2/oauth/authorize?client_id=6593&redirect_uri=http://localhost:5000/anilist&response_type=code");
-                            start_rocket(pkce).await;
-                        }
-                        ErrorKind::PermissionDenied => {
-                            panic!("You don't have permission to open the config file")
-                        }
-                        other_error => {
-                            panic!("Unhandled error: {:?}", other_error);
-                        }
-                    },
-                    Ok(mut file) => {
-                        let mut file_string = String::new();
-                        file.read_to_string(&mut file_string).unwrap();
-                        let config: TomlConfig = toml::from_str(&file_string).unwrap();
-                        if let Some(anilist) = config.anilist {
-                            let list = anilist_queries::get_list(&anilist, list_type).await;
-                            save_to_file::write_list_to_file(
-                                &list,
-                                (anilist.user_id, &anilist.user_name),
-                                list_type,
-                            );
-                        } else {
-                            println!("Go here to authenticate: https://anilist.co/api/v2/oauth/authorize?client_id=6593&redirect_uri=http://localhost:5000/anilist&response_type=code");
-                            start_rocket(pkce).await;
-                        }
-                    }
+            let mut config = config::load_config()?;
+            // also gets the score format the user has now. The backup records it,
+            // and MAL's format converts the scores with it
+            login(&mut config, Service::Anilist, io::stdin().is_terminal()).await?;
+            let urls = ApiUrls::new(&config);
+            let format_name = backup_matches.value_of("format").unwrap();
+            let scores = config.score_conversion();
+            let format = Format::from_arg(
+                format_name,
+                backup_matches.is_present("compact"),
+                backup_matches.value_of("columns"),
+                scores,
+            )?;
+            let anilist = config.anilist.as_ref().unwrap();
+            // the whole collection rather than `fetch_list`, so the backup has the custom lists too
+            let list = anilist_queries::get_list(anilist, &urls, list_type).await?;
+            let snapshot_dir = backup_dir(&config)?;
+            let output = match backup_matches.value_of("output") {
+                Some("-") => Output::Stdout,
+                Some(path) => Output::File(PathBuf::from(path)),
+                None => Output::File(snapshots::new_snapshot_path(
+                    &snapshot_dir,
+                    list_type,
+                    format.extension(),
+                )),
+            };
+            let user = User {
+                id: anilist.user_id,
+                name: anilist.user_name.clone(),
+            };
+            save_to_file::write_list_to_file(
+                &list.list_entries(),
+                list.lists.iter().map(ListSection::new).collect(),
+                &user,
+                list_type,
+                scores.format,
+                &format,
+                &output,
+            )?;
+            if let Output::File(file_path) = &output {
+                println!("Saved the backup to {}", file_path.display());
+            }
+
+            if !retention.is_empty() {
+                for snapshot in snapshots::prune(&snapshot_dir, list_type, &retention)? {
+                    println!("Removed {}", snapshot.path.display());
                 }
             }
         }
-        ("update", Some(update_matches)) => {
-            let list_type = match update_matches.value_of("list type") {
-                Some(list_type) => {
-                    let list_type_uppercase = list_type.to_uppercase();
-                    let list_type: MediaType;
-                    if list_type_uppercase == "ANIME" {
-                        list_type = MediaType::ANIME
-                    } else if list_type_uppercase == "MANGA" {
-                        list_type = MediaType::MANGA
-                    } else {
-                        panic!("The value for 'list type' needs to be either 'anime' or 'manga', case insensitive")
-                    }
-                    list_type
+        ("backups", Some(backups_matches)) => match backups_matches.subcommand() {
+            ("list", Some(list_matches)) => {
+                let list_type = list_matches
+                    .value_of("list type")
+                    .map(|list_type| parse_list_type(Some(list_type)))
+                    .transpose()?;
+                let snapshot_dir = backup_dir(&config::load_config()?)?;
+                let snapshots = snapshots::list_snapshots(&snapshot_dir, list_type);
+                if snapshots.is_empty() {
+                    println!("No backups in {}", snapshot_dir.display());
                 }
-                None => {
-                    panic!("Didn't get list type");
+                for snapshot in snapshots {
+                    println!(
+                        "{}  {:?}  {}",
+                        snapshot.taken_at.format("%Y-%m-%d %H:%M:%S UTC"),
+                        snapshot.list_type,
+                        snapshot.path.display()
+                    );
                 }
+            }
+            _ => {
+                return Err(Error::Input(
+                    "Missing a subcommand. Run with --help to see them".to_string(),
+                ));
+            }
+        },
+        ("restore", Some(restore_matches)) => {
+            let backup =
+                save_to_file::read_backup(Path::new(restore_matches.value_of("file").unwrap()))?;
+            let list_type = backup.user_section.list_type;
+
+            let target = match restore_matches.value_of("target").unwrap() {
+                "mal" => Service::Mal,
+                _ => Service::Anilist,
             };
+            let can_log_in = !restore_matches.is_present("yes")
+                && !restore_matches.is_present("json")
+                && io::stdin().is_terminal();
 
-            let file = fs::OpenOptions::new()
-                .read(true)
-                .write(true)
-                .open(file_path);
-
-            match file {
-                Err(error) => match error.kind() {
-                    ErrorKind::NotFound => {
-                        let mut auth_link = String::new();
-                        auth_link.push_str(
-                                "https://myanimelist.net/v1/oauth2/authorize?response_type=code&client_id=",
-                            );
-                        auth_link.push_str(&env::var("MAL_CLIENT_ID").unwrap());
-                        auth_link.push_str("&code_challenge=");
-                        auth_link.push_str(&pkce.code_challenge);
-                        println!("Go here to authenticate: {}", auth_link);
-
-                        start_rocket(pkce).await;
-                    }
-                    ErrorKind::PermissionDenied => {
-                        panic!("You don't have permission to open the config file")
+            let mut config = config::load_config()?;
+            login(&mut config, target, can_log_in).await?;
+            let urls = ApiUrls::new(&config);
+            // the backup's scores are converted to this format before they're compared
+            let scores = match target {
+                // AniList takes scores in the format the user has now, which logging in got
+                Service::Anilist => config.score_conversion(),
+                // converting straight from the backup's format loses the least. Older backups
+                // don't have it, so the format saved when last logged in to AniList is used
+                Service::Mal => {
+                    let mut scores = config.score_conversion();
+                    if let Some(score_format) = backup.user_section.score_format {
+                        scores.format = score_format;
                     }
-                    other_error => {
-                        panic!("Unhandled error: {:?}", other_error);
+                    scores
+                }
+            };
+            let provider: Box<dyn ListProvider> = match target {
+                Service::Mal => Box::new(MalClient {
+                    config: config.myanimelist.as_ref().unwrap(),
+                    urls: &urls,
+                    page_size: mal_queries::MAX_PAGE_SIZE,
+                    scores,
+                }),
+                Service::Anilist => {
+                    let anilist_config = config.anilist.as_ref().unwrap();
+                    if anilist_config.user_id != backup.user_section.user_id {
+                        eprintln!(
+                            "This backup is from {}, but you're logged in as {}",
+                            backup.user_section.username, anilist_config.user_name
+                        );
                     }
-                },
-                Ok(mut file) => {
-                    let mut file_string = String::new();
-                    file.read_to_string(&mut file_string).unwrap();
-                    let config: TomlConfig = toml::from_str(&file_string).unwrap();
-                    // just going to get the current list from mal before trying to update anything
-                    let mal_list = match &config.myanimelist {
-                        Some(mal) => mal_queries::get_list(&mal, list_type).await,
-                        None => {
-                            let mut auth_link = String::new();
-                            auth_link.push_str(
-                                "https://myanimelist.net/v1/oauth2/authorize?response_type=code&client_id=",
-                            );
-                            auth_link.push_str(&env::var("MAL_CLIENT_ID").unwrap());
-                            auth_link.push_str("&code_challenge=");
-                            auth_link.push_str(&pkce.code_challenge);
-                            println!("Go here to authenticate: {}", auth_link);
-
-                            start_rocket(pkce).await;
-                            return;
-                        }
-                    };
-                    let anilist_list = match config.anilist {
-                        Some(anilist) => anilist_queries::get_list(&anilist, list_type).await,
-                        None => {
-                            println!("Go here to authenticate: https://anilist.co/api/v2/oauth/authorize?client_id=6593&redirect_uri=http://localhost:5000/anilist&response_type=code");
-                            start_rocket(pkce).await;
-                            return;
-                        }
-                    };
-                    // compare them both and update the one that's behind
-                    let mal_config = config.myanimelist.unwrap();
-                    do_update(mal_config, mal_list, anilist_list, list_type).await;
+                    Box::new(AnilistClient {
+                        config: anilist_config,
+                        urls: &urls,
+                    })
+                }
+            };
+            let live = provider.fetch_list(list_type).await?;
+            let plan = restore::build_plan(&backup, &live, target, &scores);
+
+            if restore_matches.is_present("dry run") {
+                if restore_matches.is_present("json") {
+                    println!("{}", serde_json::to_string_pretty(&plan).unwrap());
+                } else {
+                    sync::print_plan(&plan);
                 }
+                return Ok(());
             }
+            sync::apply_plan(
+                &[provider.as_ref()],
+                &plan,
+                restore_matches.is_present("yes"),
+            )
+            .await?;
         }
-        _ => {
-            panic!("No matches");
-        }
-    }
-}
+        ("diff", Some(diff_matches)) => {
+            let old = save_to_file::read_backup(Path::new(diff_matches.value_of("old").unwrap()))?;
+            let new = save_to_file::read_backup(Path::new(diff_matches.value_of("new").unwrap()))?;
+            if old.user_section.list_type != new.user_section.list_type {
+                return Err(Error::Input(
+                    "Can't compare an anime backup with a manga backup".to_string(),
+                ));
+            }
 
-async fn do_update(
-    mal_config: config::MALConfig,
-    mal_list: mal_queries::List,
-    anilist_list: anilist_queries::Lists,
-    list_type: MediaType,
-) {
-    for list in anilist_list.lists {
-        for anilist_entry in list.entries {
-            // iterate through mal list and compare their id with anilist_entry.media.id_mal
-            // only if anilist_entry.media.id_mal exists
-            match anilist_entry.media.id_mal {
-                Some(id_mal) => {
-                    let mut mal_iter = mal_list.data.iter();
-                    let mal_entry_exists = mal_iter.find(|x| x.node.id == id_mal);
-                    match mal_entry_exists {
-                        Some(mal_entry) => {
-                            // check to see if progress is different
-                            let mal_progress = match list_type {
-                                MediaType::ANIME => {
-                                    mal_entry.list_status.num_episodes_watched.unwrap()
-                                }
-                                MediaType::MANGA => {
-                                    mal_entry.list_status.num_chapters_read.unwrap()
-                                }
-                            };
-                            if mal_progress != anilist_entry.progress {
-                                println!("Title: {}", anilist_entry.media.title.user_preferred);
-                                println!("Anilist Progress: {}", anilist_entry.progress);
-                                println!("Myanimelist progress: {}", mal_progress);
-                                println!("Status: {:?}", anilist_entry.status);
-                                println!("Update MAL? [y/n]");
-                                let mut buffer = String::new();
-                                io::stdin().read_line(&mut buffer).unwrap();
-                                // verify input
-                                let buffer = buffer.trim();
-                                if buffer == "n" {
-                                    println!("");
-                                    continue;
-                                } else if buffer == "y" {
-                                    // update
-                                    let updated_status =
-                                        get_updated_status(anilist_entry.status, list_type);
-                                    let updated_score = anilist_entry.score.floor() as u8;
-                                    mal_queries::update_entry(
-                                        &mal_config,
-                                        mal_entry.node.id,
-                                        updated_status,
-                                        anilist_entry.progress,
-                                        updated_score,
-                                        list_type,
-                                    )
-                                    .await;
-                                } else {
-                                    // treat as no for now because it doesn't matter right now
-                                    continue;
-                                }
-                            }
-                        }
-                        None => {
-                            // if it doesn't exist, make a new entry
-                            println!("Title: {}", anilist_entry.media.title.user_preferred);
-                            println!("Progress: {}", anilist_entry.progress);
-                            println!("Status: {:?}", anilist_entry.status);
-                            println!("You don't currently have an entry for this on MyAnimeList");
-                            println!("Update MAL? [y/n]");
-                            let mut buffer = String::new();
-                            io::stdin().read_line(&mut buffer).unwrap();
-                            // verify input
-                            let buffer = buffer.trim();
-                            if buffer == "n" {
-                                println!("");
-                                continue;
-                            } else if buffer == "y" {
-                                // update
-                                let updated_status =
-                                    get_updated_status(anilist_entry.status, list_type);
-                                let updated_score = anilist_entry.score.floor() as u8;
-                                mal_queries::update_entry(
-                                    &mal_config,
-                                    id_mal,
-                                    updated_status,
-                                    anilist_entry.progress,
-                                    updated_score,
-                                    list_type,
-                                )
-                                .await;
-                            } else {
-                                // treat as no for now because it doesn't matter right now
-                                continue;
-                            }
-                        }
+            let diff = diff::diff_backups(&old, &new);
+            if diff_matches.is_present("json") {
+                println!("{}", serde_json::to_string_pretty(&diff).unwrap());
+            } else {
+                diff::print_diff(&diff);
+            }
+        }
+        ("update", Some(update_matches)) => {
+            run_sync(update_matches, Direction::AnilistToMal).await?;
+        }
+        ("sync", Some(sync_matches)) => {
+            let direction = Direction::from_arg(sync_matches.value_of("direction").unwrap())?;
+            run_sync(sync_matches, direction).await?;
+        }
+        ("mapping", Some(mapping_matches)) => {
+            let mut mappings = mappings::load_mappings()?;
+            match mapping_matches.subcommand() {
+                ("add", Some(add_matches)) => {
+                    let mapping = Mapping {
+                        anilist_id: parse_id(add_matches.value_of("anilist id"), "anilist id")?,
+                        mal_id: parse_id(add_matches.value_of("mal id"), "mal id")?,
+                    };
+                    let message = if add_matches.is_present("block") {
+                        mappings.add_block(mapping);
+                        "won't be mapped to"
+                    } else {
+                        mappings.add_override(mapping);
+                        "is now mapped to"
+                    };
+                    mappings::save_mappings(&mappings)?;
+                    println!(
+                        "AniList {} {} MAL {}",
+                        mapping.anilist_id, message, mapping.mal_id
+                    );
+                }
+                ("remove", Some(remove_matches)) => {
+                    let anilist_id = parse_id(remove_matches.value_of("anilist id"), "anilist id")?;
+                    if mappings.remove(anilist_id) {
+                        mappings::save_mappings(&mappings)?;
+                        println!("Removed the mappings for AniList {}", anilist_id);
+                    } else {
+                        println!("There aren't any mappings for AniList {}", anilist_id);
+                    }
+                }
+                ("list", Some(_)) => {
+                    if mappings.overrides.is_empty() && mappings.blocks.is_empty() {
+                        println!("No mappings in {}", mappings::mappings_path()?.display());
+                    }
+                    for mapping in mappings.overrides.iter() {
+                        println!("AniList {} -> MAL {}", mapping.anilist_id, mapping.mal_id);
                     }
+                    for mapping in mappings.blocks.iter() {
+                        println!(
+                            "AniList {} -> MAL {} (blocked)",
+                            mapping.anilist_id, mapping.mal_id
+                        );
+                    }
+                }
+                _ => {
+                    return Err(Error::Input(
+                        "Missing a subcommand. Run with --help to see them".to_string(),
+                    ));
                 }
-                None => continue,
             }
         }
+        _ => {
+            return Err(Error::Input(
+                "Missing a subcommand. Run with --help to see them".to_string(),
+            ));
+        }
     }
-}
-
-fn get_updated_status(
-    anilist_status: anilist_queries::MediaListStatus,
-    list_type: MediaType,
-) -> mal_queries::Status {
-    match anilist_status {
-        anilist_queries::MediaListStatus::Completed => mal_queries::Status::completed,
-        anilist_queries::MediaListStatus::Dropped => mal_queries::Status::dropped,
-        anilist_queries::MediaListStatus::Paused => mal_queries::Status::on_hold,
-        anilist_queries::MediaListStatus::Planning => match list_type {
-            MediaType::ANIME => mal_queries::Status::plan_to_watch,
-            MediaType::MANGA => mal_queries::Status::plan_to_read,
-        },
-        anilist_queries::MediaListStatus::Current => match list_type {
-            MediaType::ANIME => mal_queries::Status::watching,
-            MediaType::MANGA => mal_queries::Status::reading,
-        },
-        anilist_queries::MediaListStatus::Repeating => mal_queries::Status::completed,
-    }
+    Ok(())
 }
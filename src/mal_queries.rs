@@ -1,7 +1,14 @@
+use async_trait::async_trait;
+use serde::de::DeserializeOwned;
 use serde::Deserialize;
 
-use super::anilist_queries::MediaType;
-use super::config::MALConfig;
+use super::anilist_queries::{FuzzyDate, MediaListStatus, MediaType};
+use super::config::{ApiUrls, MALConfig};
+use super::error::{Error, Result};
+use super::provider::{EntryValues, ListEntry, ListProvider, Service, User};
+use super::scores::ScoreConversion;
+
+const SERVICE: &str = "MyAnimeList";
 
 #[derive(Deserialize, Debug)]
 pub struct List {
@@ -30,6 +37,7 @@ struct Picture {
     large: String,
 }
 
+#[allow(dead_code)] // not every field is compared when updating yet
 #[derive(Deserialize, Debug)]
 pub struct ListStatus {
     // change to enum
@@ -39,11 +47,18 @@ pub struct ListStatus {
     pub num_chapters_read: Option<u32>,
     pub num_volumes_read: Option<u32>,
     pub is_rewatching: Option<bool>,
+    pub is_rereading: Option<bool>,
+    pub num_times_rewatched: Option<u32>,
+    pub num_times_reread: Option<u32>,
+    /// Can be just the year, or the year and month
+    pub start_date: Option<String>,
+    pub finish_date: Option<String>,
+    pub comments: Option<String>,
     pub updated_at: String,
 }
 
 #[allow(non_camel_case_types)]
-#[derive(Deserialize, Debug, Copy, Clone)]
+#[derive(Deserialize, Debug, Copy, Clone, PartialEq)]
 pub enum Status {
     watching,
     completed,
@@ -54,102 +69,424 @@ pub enum Status {
     plan_to_read,
 }
 
+impl MALEntry {
+    /// The score is converted to the user's AniList score format with `scores`
+    pub fn to_list_entry(&self, list_type: MediaType, scores: &ScoreConversion) -> ListEntry {
+        let list_status = &self.list_status;
+        let (progress, repeating, repeat) = match list_type {
+            MediaType::ANIME => (
+                list_status.num_episodes_watched,
+                list_status.is_rewatching,
+                list_status.num_times_rewatched,
+            ),
+            MediaType::MANGA => (
+                list_status.num_chapters_read,
+                list_status.is_rereading,
+                list_status.num_times_reread,
+            ),
+        };
+        // MAL keeps rewatching separate from the status, where AniList has a status for it
+        let status = match repeating {
+            Some(true) => MediaListStatus::Repeating,
+            _ => get_anilist_status(list_status.status),
+        };
+        let mut entry = ListEntry::new(
+            self.node.title.clone(),
+            status,
+            scores.from_mal(list_status.score),
+            progress.unwrap_or(0),
+        );
+        entry.mal_id = Some(self.node.id);
+        entry.mal_score = Some(list_status.score);
+        entry.repeat = repeat.unwrap_or(0);
+        entry.progress_volumes = list_status.num_volumes_read;
+        entry.started_at = parse_date(list_status.start_date.as_deref());
+        entry.completed_at = parse_date(list_status.finish_date.as_deref());
+        // MAL sends an empty string when there aren't any
+        entry.notes = list_status
+            .comments
+            .clone()
+            .filter(|comments| !comments.is_empty());
+        // entries without a timestamp count as the oldest when syncing
+        entry.updated_at = chrono::DateTime::parse_from_rfc3339(&list_status.updated_at)
+            .map(|date| date.timestamp())
+            .ok();
+        entry
+    }
+}
+
 #[derive(Deserialize, Debug)]
 pub struct Paging {
     pub next: Option<String>,
 }
 
 #[derive(Deserialize, Debug)]
-pub struct Error {
+struct ErrorResp {
     message: String,
     error: String,
 }
 
+/// The most entries MAL will return in a single page
+pub const MAX_PAGE_SIZE: u32 = 1000;
+
 // will probably need a different function for manga because the return fields are different
 // or maybe just make this one do more ???
-pub async fn get_list(config: &MALConfig, list_type: MediaType) -> List {
-    let auth_header = format!("Bearer {}", config.access_token);
+/// Gets the whole list, following `paging.next` until every page has been fetched.
+/// The returned `List` has all of the pages merged into `data`
+pub async fn get_list(
+    config: &MALConfig,
+    urls: &ApiUrls,
+    list_type: MediaType,
+    page_size: u32,
+) -> Result<List> {
+    let url = format!(
+        "{}/users/@me/{}list?fields={}&limit={}",
+        urls.mal_api,
+        media_path(list_type),
+        list_status_fields(list_type),
+        page_size
+    );
 
-    let url = match list_type {
-        MediaType::ANIME => {
-            "https://api.myanimelist.net/v2/users/@me/animelist?fields=list_status&limit=1000"
-        }
-        MediaType::MANGA => {
-            "https://api.myanimelist.net/v2/users/@me/mangalist?fields=list_status&limit=1000"
-        }
-    };
+    get_all_pages(config, url).await
+}
 
+async fn get_all_pages(config: &MALConfig, first_page: String) -> Result<List> {
     let client = reqwest::Client::new();
-    let res = client
-        .get(url)
-        .header("Authorization", auth_header)
-        .send()
-        .await
-        .unwrap()
-        .text()
-        .await
-        .unwrap();
 
-    // this will error when the token expires
-    let result: List = serde_json::from_str(&res).unwrap();
+    let mut data = Vec::new();
+    let mut next = Some(first_page);
+    // the `next` link keeps the fields and limit from the first request
+    while let Some(url) = next {
+        let page: List = send(client.get(&url), config).await?;
+        data.extend(page.data);
+        next = page.paging.next;
+    }
+
+    Ok(List {
+        data,
+        paging: Paging { next: None },
+    })
+}
+
+/// The list status fields to request. Not all of them are sent unless they're asked for
+fn list_status_fields(list_type: MediaType) -> &'static str {
+    match list_type {
+        MediaType::ANIME => "list_status{status,score,num_episodes_watched,is_rewatching,num_times_rewatched,start_date,finish_date,comments,updated_at}",
+        MediaType::MANGA => "list_status{status,score,num_chapters_read,num_volumes_read,is_rereading,num_times_reread,start_date,finish_date,comments,updated_at}",
+    }
+}
+
+/// The values sent to MAL when an entry is updated, in MAL's terms
+#[derive(Debug, Clone, PartialEq)]
+pub struct ListUpdate {
+    pub status: Status,
+    pub score: u8,
+    /// Episodes for anime and chapters for manga
+    pub progress: u32,
+    /// Only sent for manga
+    pub progress_volumes: u32,
+    /// Whether it's being rewatched or reread. MAL only has this for completed entries
+    pub repeating: bool,
+    /// How many times it's been rewatched or reread
+    pub repeat: u32,
+    /// Formatted with `format_date`. Empty clears the date
+    pub start_date: String,
+    pub finish_date: String,
+    /// Cut down to `MAX_COMMENTS_LENGTH`
+    pub comments: String,
+}
+
+impl ListUpdate {
+    pub fn new(values: &EntryValues, list_type: MediaType, scores: &ScoreConversion) -> ListUpdate {
+        ListUpdate {
+            status: get_updated_status(values.status, list_type),
+            score: scores.to_mal(values.score),
+            progress: values.progress,
+            progress_volumes: values.progress_volumes.unwrap_or(0),
+            repeating: values.status == MediaListStatus::Repeating,
+            repeat: values.repeat,
+            start_date: format_date(values.started_at),
+            finish_date: format_date(values.completed_at),
+            comments: mal_comments(values.notes.as_deref()),
+        }
+    }
+}
+
+/// The most characters MAL keeps in an entry's comments
+pub const MAX_COMMENTS_LENGTH: usize = 4096;
 
-    result
+/// Notes the way MAL would store them, with anything past `MAX_COMMENTS_LENGTH` cut off
+pub fn mal_comments(notes: Option<&str>) -> String {
+    notes
+        .unwrap_or("")
+        .chars()
+        .take(MAX_COMMENTS_LENGTH)
+        .collect()
+}
+
+/// Reads a date as MAL sends it, `YYYY-MM-DD`, `YYYY-MM` or `YYYY`
+pub fn parse_date(date: Option<&str>) -> FuzzyDate {
+    let mut parts = date.unwrap_or("").split('-');
+    FuzzyDate {
+        year: parts.next().and_then(|year| year.parse().ok()),
+        month: parts.next().and_then(|month| month.parse().ok()),
+        day: parts.next().and_then(|day| day.parse().ok()),
+    }
+}
+
+/// Writes a date the way MAL takes it. MAL can only leave out the end of a date,
+/// so anything after the first missing part is dropped, e.g. a day without a month
+pub fn format_date(date: FuzzyDate) -> String {
+    match (date.year, date.month, date.day) {
+        (Some(year), Some(month), Some(day)) => format!("{:04}-{:02}-{:02}", year, month, day),
+        (Some(year), Some(month), None) => format!("{:04}-{:02}", year, month),
+        (Some(year), None, _) => format!("{:04}", year),
+        (None, _, _) => String::new(),
+    }
 }
 
 pub async fn update_entry(
     config: &MALConfig,
+    urls: &ApiUrls,
     id: u32,
-    status: Status,
-    progress: u32,
-    score: u8,
+    update: &ListUpdate,
     list_type: MediaType,
-) {
-    let auth_header = format!("Bearer {}", config.access_token);
+) -> Result<()> {
+    let url = list_status_url(urls, id, list_type);
 
-    let url = match list_type {
-        MediaType::ANIME => {
-            format!("https://api.myanimelist.net/v2/anime/{}/my_list_status", id)
-        }
-        MediaType::MANGA => {
-            format!("https://api.myanimelist.net/v2/manga/{}/my_list_status", id)
-        }
+    let (progress, repeating, repeat) = match list_type {
+        MediaType::ANIME => (
+            "num_watched_episodes",
+            "is_rewatching",
+            "num_times_rewatched",
+        ),
+        MediaType::MANGA => ("num_chapters_read", "is_rereading", "num_times_reread"),
     };
+    let mut form = vec![
+        ("status", format!("{:?}", update.status)),
+        ("score", update.score.to_string()),
+        (progress, update.progress.to_string()),
+    ];
+    if list_type == MediaType::MANGA {
+        form.push(("num_volumes_read", update.progress_volumes.to_string()));
+    }
+    form.push((repeating, update.repeating.to_string()));
+    form.push((repeat, update.repeat.to_string()));
+    form.push(("start_date", update.start_date.clone()));
+    form.push(("finish_date", update.finish_date.clone()));
+    form.push(("comments", update.comments.clone()));
 
-    let body = match list_type {
-        MediaType::ANIME => {
-            format!(
-                "status={:?}&score={}&num_watched_episodes={}",
-                status, score, progress
-            )
-        }
-        MediaType::MANGA => {
-            format!(
-                "status={:?}&score={}&num_chapters_read={}",
-                status, score, progress
-            )
-        }
-    };
+    let request = reqwest::Client::new().patch(url).form(&form);
+    let _: serde_json::Value = send(request, config).await?;
+    Ok(())
+}
 
-    let client = reqwest::Client::new();
-    let res = client
-        .patch(url)
-        .header("Authorization", auth_header)
-        .header("Content-Type", "application/x-www-form-urlencoded")
-        .body(body)
+/// The part of the URL that differs between anime and manga
+fn media_path(list_type: MediaType) -> &'static str {
+    match list_type {
+        MediaType::ANIME => "anime",
+        MediaType::MANGA => "manga",
+    }
+}
+
+fn list_status_url(urls: &ApiUrls, id: u32, list_type: MediaType) -> String {
+    format!(
+        "{}/{}/{}/my_list_status",
+        urls.mal_api,
+        media_path(list_type),
+        id
+    )
+}
+
+/// Sends the request with the user's token and parses the response.
+/// Error responses are returned as `Error::Api`, or `Error::AuthExpired` if the token was rejected
+async fn send<T: DeserializeOwned>(
+    request: reqwest::RequestBuilder,
+    config: &MALConfig,
+) -> Result<T> {
+    let res = request
+        .header("Authorization", format!("Bearer {}", config.access_token))
         .send()
-        .await
-        .unwrap()
-        .text()
-        .await
-        .unwrap();
-
-    let result: serde_json::Result<Error> = serde_json::from_str(&res);
-    match result {
-        Ok(error) => {
-            println!("\n Error: {}. {} \n", error.error, error.message);
-        }
-        Err(_) => {
-            println!("\n Update complete \n");
-        }
-    };
+        .await?;
+    if res.status() == reqwest::StatusCode::UNAUTHORIZED {
+        return Err(Error::AuthExpired(SERVICE));
+    }
+    let success = res.status().is_success();
+    let res = res.text().await?;
+
+    if !success {
+        return Err(match serde_json::from_str::<ErrorResp>(&res) {
+            Ok(error) => Error::Api(SERVICE, format!("{}. {}", error.error, error.message)),
+            Err(_) => Error::Api(SERVICE, res),
+        });
+    }
+    serde_json::from_str(&res).map_err(|error| Error::unexpected_response(SERVICE, error))
+}
+
+pub async fn delete_entry(
+    config: &MALConfig,
+    urls: &ApiUrls,
+    id: u32,
+    list_type: MediaType,
+) -> Result<()> {
+    let url = list_status_url(urls, id, list_type);
+
+    let _: serde_json::Value = send(reqwest::Client::new().delete(url), config).await?;
+    Ok(())
+}
+
+#[derive(Deserialize, Debug)]
+struct MALUser {
+    id: u32,
+    name: String,
+}
+
+pub async fn get_user(config: &MALConfig, urls: &ApiUrls) -> Result<User> {
+    let request = reqwest::Client::new().get(format!("{}/users/@me", urls.mal_api));
+    let user: MALUser = send(request, config).await?;
+    Ok(User {
+        id: user.id,
+        name: user.name,
+    })
+}
+
+#[derive(Deserialize, Debug)]
+struct SearchResults {
+    data: Vec<SearchResult>,
+}
+
+#[derive(Deserialize, Debug)]
+struct SearchResult {
+    node: SearchNode,
+}
+
+#[derive(Deserialize, Debug)]
+struct SearchNode {
+    id: u32,
+    title: String,
+    media_type: Option<String>,
+    /// Can be just the year, or the year and month
+    start_date: Option<String>,
+}
+
+/// A possible match from searching MAL
+#[derive(Debug, Clone)]
+pub struct Candidate {
+    pub id: u32,
+    pub title: String,
+    /// e.g. `tv`, `movie`, `manga` or `light_novel`
+    pub media_type: Option<String>,
+    pub start_year: Option<i32>,
+}
+
+/// The longest search MAL accepts
+const MAX_QUERY_LENGTH: usize = 64;
+
+/// Searches MAL's anime or manga by title
+pub async fn search(
+    config: &MALConfig,
+    urls: &ApiUrls,
+    query: &str,
+    list_type: MediaType,
+) -> Result<Vec<Candidate>> {
+    let query: String = query.chars().take(MAX_QUERY_LENGTH).collect();
+    let request = reqwest::Client::new()
+        .get(format!("{}/{}", urls.mal_api, media_path(list_type)))
+        .query(&[
+            ("q", query.as_str()),
+            ("limit", "10"),
+            ("fields", "media_type,start_date"),
+        ]);
+    let results: SearchResults = send(request, config).await?;
+
+    Ok(results
+        .data
+        .into_iter()
+        .map(|result| Candidate {
+            id: result.node.id,
+            title: result.node.title,
+            media_type: result.node.media_type,
+            start_year: result
+                .node
+                .start_date
+                .and_then(|date| date.get(..4)?.parse().ok()),
+        })
+        .collect())
+}
+
+pub fn get_updated_status(anilist_status: MediaListStatus, list_type: MediaType) -> Status {
+    match anilist_status {
+        MediaListStatus::Completed => Status::completed,
+        MediaListStatus::Dropped => Status::dropped,
+        MediaListStatus::Paused => Status::on_hold,
+        MediaListStatus::Planning => match list_type {
+            MediaType::ANIME => Status::plan_to_watch,
+            MediaType::MANGA => Status::plan_to_read,
+        },
+        MediaListStatus::Current => match list_type {
+            MediaType::ANIME => Status::watching,
+            MediaType::MANGA => Status::reading,
+        },
+        MediaListStatus::Repeating => Status::completed,
+    }
+}
+
+pub fn get_anilist_status(mal_status: Status) -> MediaListStatus {
+    match mal_status {
+        Status::watching | Status::reading => MediaListStatus::Current,
+        Status::completed => MediaListStatus::Completed,
+        Status::on_hold => MediaListStatus::Paused,
+        Status::dropped => MediaListStatus::Dropped,
+        Status::plan_to_watch | Status::plan_to_read => MediaListStatus::Planning,
+    }
+}
+
+pub struct MalClient<'a> {
+    pub config: &'a MALConfig,
+    pub urls: &'a ApiUrls,
+    /// How many entries to request per page, up to `MAX_PAGE_SIZE`
+    pub page_size: u32,
+    /// Scores are converted with this when they're fetched and updated
+    pub scores: ScoreConversion,
+}
+
+impl MalClient<'_> {
+    /// See `search`
+    pub async fn search(&self, query: &str, list_type: MediaType) -> Result<Vec<Candidate>> {
+        search(self.config, self.urls, query, list_type).await
+    }
+}
+
+#[async_trait]
+impl ListProvider for MalClient<'_> {
+    fn service(&self) -> Service {
+        Service::Mal
+    }
+
+    async fn whoami(&self) -> Result<User> {
+        get_user(self.config, self.urls).await
+    }
+
+    async fn fetch_list(&self, list_type: MediaType) -> Result<Vec<ListEntry>> {
+        let list = get_list(self.config, self.urls, list_type, self.page_size).await?;
+        Ok(list
+            .data
+            .iter()
+            .map(|entry| entry.to_list_entry(list_type, &self.scores))
+            .collect())
+    }
+
+    async fn update_entry(
+        &self,
+        list_type: MediaType,
+        id: u32,
+        values: &EntryValues,
+    ) -> Result<()> {
+        let update = ListUpdate::new(values, list_type, &self.scores);
+        update_entry(self.config, self.urls, id, &update, list_type).await
+    }
+
+    async fn delete_entry(&self, list_type: MediaType, id: u32) -> Result<()> {
+        delete_entry(self.config, self.urls, id, list_type).await
+    }
 }
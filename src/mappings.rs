@@ -0,0 +1,111 @@
+use std::fs;
+use std::io::ErrorKind;
+use std::path::PathBuf;
+
+use serde::{Deserialize, Serialize};
+
+use super::config;
+use super::error::{Error, Result};
+use super::provider::ListEntry;
+
+/// AniList to MAL id pairs the user has set by hand, for when AniList's `idMal` is wrong
+#[derive(Deserialize, Serialize, Debug, Default)]
+pub struct Mappings {
+    /// Used instead of whatever AniList links the media to
+    #[serde(default, rename = "override", skip_serializing_if = "Vec::is_empty")]
+    pub overrides: Vec<Mapping>,
+    /// Pairs that are never used, even if AniList links them
+    #[serde(default, rename = "block", skip_serializing_if = "Vec::is_empty")]
+    pub blocks: Vec<Mapping>,
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone, Copy, PartialEq)]
+pub struct Mapping {
+    pub anilist_id: u32,
+    pub mal_id: u32,
+}
+
+impl Mappings {
+    /// The MAL id to use for the media, given the one AniList links it to
+    pub fn mal_id(&self, anilist_id: u32, id_mal: Option<u32>) -> Option<u32> {
+        match self
+            .overrides
+            .iter()
+            .find(|mapping| mapping.anilist_id == anilist_id)
+        {
+            Some(mapping) => Some(mapping.mal_id),
+            None => id_mal.filter(|id_mal| !self.is_blocked(anilist_id, *id_mal)),
+        }
+    }
+
+    /// The AniList id overridden to map to `mal_id`, if there is one
+    pub fn anilist_id(&self, mal_id: u32) -> Option<u32> {
+        self.overrides
+            .iter()
+            .find(|mapping| mapping.mal_id == mal_id)
+            .map(|mapping| mapping.anilist_id)
+    }
+
+    pub fn is_blocked(&self, anilist_id: u32, mal_id: u32) -> bool {
+        self.blocks.contains(&Mapping { anilist_id, mal_id })
+    }
+
+    /// Sets the MAL id of AniList entries from the overrides and drops blocked ones
+    pub fn apply(&self, entries: &mut [ListEntry]) {
+        for entry in entries.iter_mut() {
+            if let Some(anilist_id) = entry.anilist_id {
+                entry.mal_id = self.mal_id(anilist_id, entry.mal_id);
+            }
+        }
+    }
+
+    /// Replaces any override the AniList id already has
+    pub fn add_override(&mut self, mapping: Mapping) {
+        self.overrides
+            .retain(|existing| existing.anilist_id != mapping.anilist_id);
+        self.overrides.push(mapping);
+    }
+
+    pub fn add_block(&mut self, mapping: Mapping) {
+        if !self.blocks.contains(&mapping) {
+            self.blocks.push(mapping);
+        }
+    }
+
+    /// Removes every override and block for the AniList id. Returns whether there were any
+    pub fn remove(&mut self, anilist_id: u32) -> bool {
+        let count = self.overrides.len() + self.blocks.len();
+        self.overrides
+            .retain(|mapping| mapping.anilist_id != anilist_id);
+        self.blocks
+            .retain(|mapping| mapping.anilist_id != anilist_id);
+        count != self.overrides.len() + self.blocks.len()
+    }
+}
+
+pub fn mappings_path() -> Result<PathBuf> {
+    Ok(config::config_dir()?.join("mappings.toml"))
+}
+
+/// Reads the mappings file, or no mappings if there isn't one
+pub fn load_mappings() -> Result<Mappings> {
+    let file_path = mappings_path()?;
+    match fs::read_to_string(&file_path) {
+        Ok(file_string) => toml::from_str(&file_string).map_err(|error| {
+            Error::Config(format!(
+                "Couldn't parse the mappings at {}: {}",
+                file_path.display(),
+                error
+            ))
+        }),
+        Err(error) if error.kind() == ErrorKind::NotFound => Ok(Mappings::default()),
+        Err(error) => Err(Error::Io(file_path, error)),
+    }
+}
+
+pub fn save_mappings(mappings: &Mappings) -> Result<()> {
+    let file_path = mappings_path()?;
+    let dir = file_path.parent().unwrap();
+    fs::create_dir_all(dir).map_err(Error::io(dir))?;
+    fs::write(&file_path, toml::to_string(mappings).unwrap()).map_err(Error::io(&file_path))
+}
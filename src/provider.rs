@@ -0,0 +1,186 @@
+use std::collections::BTreeMap;
+
+use async_trait::async_trait;
+use serde::{Deserialize, Serialize};
+
+use super::anilist_queries::{FuzzyDate, MediaFormat, MediaListStatus, MediaType};
+use super::error::Result;
+
+#[derive(Deserialize, Serialize, Debug, Copy, Clone, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum Service {
+    Anilist,
+    Mal,
+}
+
+impl Service {
+    pub fn name(&self) -> &'static str {
+        match self {
+            Service::Anilist => "AniList",
+            Service::Mal => "MyAnimeList",
+        }
+    }
+}
+
+/// The logged in user
+#[derive(Debug, Clone)]
+pub struct User {
+    pub id: u32,
+    pub name: String,
+}
+
+/// A list entry from either service. Statuses and scores are in AniList's terms,
+/// so MAL's are converted when its list is fetched
+#[derive(Debug, Clone)]
+pub struct ListEntry {
+    pub anilist_id: Option<u32>,
+    pub mal_id: Option<u32>,
+    pub title: String,
+    pub status: MediaListStatus,
+    pub score: f32,
+    /// MAL's own 0 to 10 score, only filled in by MAL. `score` is converted from it
+    pub mal_score: Option<u8>,
+    /// Episodes for anime and chapters for manga
+    pub progress: u32,
+    pub progress_volumes: Option<u32>,
+    /// How many times it's been rewatched or reread
+    pub repeat: u32,
+    pub notes: Option<String>,
+    pub started_at: FuzzyDate,
+    pub completed_at: FuzzyDate,
+    /// Unix timestamp
+    pub updated_at: Option<i64>,
+    // everything from here on is only filled in by AniList
+    pub format: Option<MediaFormat>,
+    pub episodes: Option<u32>,
+    pub chapters: Option<u32>,
+    /// The year it started airing or being published
+    pub start_year: Option<i32>,
+    pub private: bool,
+    pub hidden_from_status_lists: bool,
+    /// The names of the custom lists it's in
+    pub custom_lists: Vec<String>,
+    pub created_at: Option<i64>,
+    pub advanced_scores: BTreeMap<String, f32>,
+}
+
+impl ListEntry {
+    /// An entry with only the required fields set
+    pub fn new(title: String, status: MediaListStatus, score: f32, progress: u32) -> ListEntry {
+        ListEntry {
+            anilist_id: None,
+            mal_id: None,
+            title,
+            status,
+            score,
+            mal_score: None,
+            progress,
+            progress_volumes: None,
+            repeat: 0,
+            notes: None,
+            started_at: FuzzyDate::default(),
+            completed_at: FuzzyDate::default(),
+            updated_at: None,
+            format: None,
+            episodes: None,
+            chapters: None,
+            start_year: None,
+            private: false,
+            hidden_from_status_lists: false,
+            custom_lists: Vec::new(),
+            created_at: None,
+            advanced_scores: BTreeMap::new(),
+        }
+    }
+
+    /// The id `service` uses for the entry's media
+    pub fn id(&self, service: Service) -> Option<u32> {
+        match service {
+            Service::Anilist => self.anilist_id,
+            Service::Mal => self.mal_id,
+        }
+    }
+
+    pub fn values(&self) -> EntryValues {
+        EntryValues {
+            status: self.status,
+            score: self.score,
+            mal_score: self.mal_score,
+            progress: self.progress,
+            progress_volumes: self.progress_volumes,
+            repeat: self.repeat,
+            started_at: self.started_at,
+            completed_at: self.completed_at,
+            notes: self.notes.clone(),
+            private: None,
+            hidden_from_status_lists: None,
+            custom_lists: None,
+            advanced_scores: None,
+        }
+    }
+
+    /// `values` with the fields only AniList has, for comparing with a backup restored to AniList
+    pub fn anilist_values(&self) -> EntryValues {
+        EntryValues {
+            private: Some(self.private),
+            hidden_from_status_lists: Some(self.hidden_from_status_lists),
+            custom_lists: Some(self.custom_lists.clone()),
+            advanced_scores: Some(self.advanced_scores.clone()),
+            ..self.values()
+        }
+    }
+}
+
+/// The values that get synced, in AniList's terms.
+/// They're converted to what MAL expects when a change is applied
+#[derive(Deserialize, Serialize, Debug, Clone, PartialEq)]
+pub struct EntryValues {
+    pub status: MediaListStatus,
+    pub score: f32,
+    /// MAL's own score, for values that came from MAL. Converting it to `score` can lose
+    /// detail, e.g. a 9 is 4 stars, so this is what's compared with MAL
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub mal_score: Option<u8>,
+    pub progress: u32,
+    /// Only for manga
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub progress_volumes: Option<u32>,
+    /// Plans saved before this was synced don't have it
+    #[serde(default)]
+    pub repeat: u32,
+    /// Any part of a date can be missing
+    #[serde(default, skip_serializing_if = "FuzzyDate::is_empty")]
+    pub started_at: FuzzyDate,
+    #[serde(default, skip_serializing_if = "FuzzyDate::is_empty")]
+    pub completed_at: FuzzyDate,
+    /// Called comments on MAL
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub notes: Option<String>,
+    // the rest are only on AniList and only set when restoring to it. `None` leaves them as they are
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub private: Option<bool>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub hidden_from_status_lists: Option<bool>,
+    /// The names of the custom lists it's in
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub custom_lists: Option<Vec<String>>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub advanced_scores: Option<BTreeMap<String, f32>>,
+}
+
+/// A service that holds anime and manga lists. Media ids are always the service's own
+#[async_trait]
+pub trait ListProvider: Send + Sync {
+    fn service(&self) -> Service;
+
+    async fn whoami(&self) -> Result<User>;
+
+    /// Every entry on the list, each one once
+    async fn fetch_list(&self, list_type: MediaType) -> Result<Vec<ListEntry>>;
+
+    /// Creates the entry if the media isn't on the list yet
+    async fn update_entry(&self, list_type: MediaType, id: u32, values: &EntryValues)
+        -> Result<()>;
+
+    async fn delete_entry(&self, list_type: MediaType, id: u32) -> Result<()>;
+}
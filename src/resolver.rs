@@ -0,0 +1,216 @@
+use std::cmp::Reverse;
+use std::fs;
+use std::io::{self, ErrorKind};
+use std::path::PathBuf;
+
+use serde::{Deserialize, Serialize};
+
+use super::anilist_queries::{MediaFormat, MediaType};
+use super::config;
+use super::error::{Error, Result};
+use super::mal_queries::{Candidate, MalClient};
+use super::mappings::Mappings;
+use super::provider::ListEntry;
+
+/// How many search results are offered as matches
+const MAX_CANDIDATES: usize = 5;
+
+/// The MAL ids the user has picked for entries AniList hasn't linked to MAL
+#[derive(Deserialize, Serialize, Debug, Default)]
+pub struct ResolvedIds {
+    #[serde(default)]
+    pub resolved: Vec<Resolved>,
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone)]
+pub struct Resolved {
+    pub anilist_id: u32,
+    /// `None` when the user said it isn't on MAL
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub mal_id: Option<u32>,
+    /// Only there so the file can be read by a person
+    pub title: String,
+}
+
+impl ResolvedIds {
+    pub fn get(&self, anilist_id: u32) -> Option<&Resolved> {
+        self.resolved
+            .iter()
+            .find(|resolved| resolved.anilist_id == anilist_id)
+    }
+}
+
+pub fn resolved_ids_path() -> Result<PathBuf> {
+    Ok(config::config_dir()?.join("resolved_ids.toml"))
+}
+
+/// Reads the saved ids, or none if nothing has been resolved yet
+pub fn load_resolved_ids() -> Result<ResolvedIds> {
+    let file_path = resolved_ids_path()?;
+    match fs::read_to_string(&file_path) {
+        Ok(file_string) => toml::from_str(&file_string).map_err(|error| {
+            Error::Config(format!(
+                "Couldn't parse the resolved ids at {}: {}",
+                file_path.display(),
+                error
+            ))
+        }),
+        Err(error) if error.kind() == ErrorKind::NotFound => Ok(ResolvedIds::default()),
+        Err(error) => Err(Error::Io(file_path, error)),
+    }
+}
+
+pub fn save_resolved_ids(ids: &ResolvedIds) -> Result<()> {
+    let file_path = resolved_ids_path()?;
+    let dir = file_path.parent().unwrap();
+    fs::create_dir_all(dir).map_err(Error::io(dir))?;
+    fs::write(&file_path, toml::to_string(ids).unwrap()).map_err(Error::io(&file_path))
+}
+
+/// Fills in the MAL id of entries AniList hasn't linked to MAL.
+/// Ids picked before are used straight away. When `interactive` is set, MAL is searched
+/// for the rest and the user picks the match, which is saved so they're only asked once.
+/// Pairs blocked in `mappings` are never used
+pub async fn resolve(
+    mal: &MalClient<'_>,
+    mappings: &Mappings,
+    entries: &mut [ListEntry],
+    list_type: MediaType,
+    interactive: bool,
+) -> Result<()> {
+    let mut ids = load_resolved_ids()?;
+
+    for entry in entries.iter_mut().filter(|entry| entry.mal_id.is_none()) {
+        let anilist_id = match entry.anilist_id {
+            Some(anilist_id) => anilist_id,
+            None => continue,
+        };
+        let resolved = ids.get(anilist_id).map(|resolved| resolved.mal_id);
+        match resolved {
+            Some(Some(mal_id)) if mappings.is_blocked(anilist_id, mal_id) => {}
+            Some(mal_id) => {
+                entry.mal_id = mal_id;
+                continue;
+            }
+            None => {}
+        }
+        if !interactive {
+            continue;
+        }
+
+        let candidates = match mal.search(&entry.title, list_type).await {
+            Ok(candidates) => rank(
+                entry,
+                candidates
+                    .into_iter()
+                    .filter(|candidate| !mappings.is_blocked(anilist_id, candidate.id))
+                    .collect(),
+            ),
+            // e.g. the title is too short for MAL to search for
+            Err(Error::Api(_, message)) => {
+                eprintln!("Couldn't search MAL for {}: {}", entry.title, message);
+                continue;
+            }
+            Err(error) => return Err(error),
+        };
+        if let Some(mal_id) = choose(entry, &candidates) {
+            entry.mal_id = mal_id;
+            ids.resolved
+                .retain(|resolved| resolved.anilist_id != anilist_id);
+            ids.resolved.push(Resolved {
+                anilist_id,
+                mal_id,
+                title: entry.title.clone(),
+            });
+            // saved after every answer so they aren't lost if the sync is stopped
+            save_resolved_ids(&ids)?;
+        }
+    }
+    Ok(())
+}
+
+/// Puts the candidates with the same format and year as the entry first and keeps the best few
+fn rank(entry: &ListEntry, mut candidates: Vec<Candidate>) -> Vec<Candidate> {
+    // the sort is stable, so ties stay in MAL's order
+    candidates.sort_by_key(|candidate| Reverse(similarity(entry, candidate)));
+    candidates.truncate(MAX_CANDIDATES);
+    candidates
+}
+
+fn similarity(entry: &ListEntry, candidate: &Candidate) -> u8 {
+    let same_format = match (entry.format, &candidate.media_type) {
+        (Some(format), Some(media_type)) => mal_media_types(format).contains(&media_type.as_str()),
+        _ => false,
+    };
+    let same_year = entry.start_year.is_some() && entry.start_year == candidate.start_year;
+    same_format as u8 * 2 + same_year as u8
+}
+
+/// The MAL media types that an AniList format can be
+fn mal_media_types(format: MediaFormat) -> &'static [&'static str] {
+    match format {
+        MediaFormat::Tv | MediaFormat::TvShort => &["tv"],
+        MediaFormat::Movie => &["movie"],
+        MediaFormat::Special => &["special"],
+        MediaFormat::Ova => &["ova"],
+        MediaFormat::Ona => &["ona"],
+        MediaFormat::Music => &["music"],
+        MediaFormat::Manga => &["manga", "manhwa", "manhua", "doujinshi", "oel"],
+        MediaFormat::Novel => &["light_novel", "novel"],
+        MediaFormat::OneShot => &["one_shot"],
+    }
+}
+
+/// Asks which candidate the entry is. `Some(None)` means it isn't on MAL,
+/// and `None` that the user skipped it for now
+fn choose(entry: &ListEntry, candidates: &[Candidate]) -> Option<Option<u32>> {
+    println!(
+        "{} ({}) isn't linked to MAL",
+        entry.title,
+        describe(
+            entry.format.map(|format| format!("{:?}", format)),
+            entry.start_year
+        )
+    );
+    if candidates.is_empty() {
+        println!("Searching MAL for the title found nothing");
+    }
+    for (i, candidate) in candidates.iter().enumerate() {
+        println!(
+            "{}. {} ({})",
+            i + 1,
+            candidate.title,
+            describe(candidate.media_type.clone(), candidate.start_year)
+        );
+    }
+
+    loop {
+        println!(
+            "Which is it? Enter its number, 'n' if it isn't on MAL, or nothing to skip it for now"
+        );
+        let mut buffer = String::new();
+        // not being able to read an answer counts as skipping
+        if io::stdin().read_line(&mut buffer).is_err() {
+            return None;
+        }
+        match buffer.trim() {
+            "" => return None,
+            "n" => return Some(None),
+            answer => match answer.parse::<usize>() {
+                Ok(i) if (1..=candidates.len()).contains(&i) => {
+                    return Some(Some(candidates[i - 1].id))
+                }
+                _ => println!("That isn't one of the options"),
+            },
+        }
+    }
+}
+
+fn describe(format: Option<String>, year: Option<i32>) -> String {
+    format!(
+        "{}, {}",
+        format.unwrap_or_else(|| "unknown format".to_string()),
+        year.map(|year| year.to_string())
+            .unwrap_or_else(|| "unknown year".to_string())
+    )
+}
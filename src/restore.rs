@@ -0,0 +1,130 @@
+use std::collections::HashMap;
+
+use super::provider::{EntryValues, ListEntry, Service};
+use super::save_to_file::{BackupToml, EntrySection};
+use super::scores::ScoreConversion;
+use super::sync::{self, Action, Change, Plan};
+
+/// Works out what needs to change on `target` for it to match the backup.
+/// Entries that are on the list but not in the backup are left alone.
+/// Entries without a MAL id can't be restored to MAL, so they're listed on stderr.
+/// The backup's scores are converted to the format in `scores`, which is also used to compare them on MAL
+pub fn build_plan(
+    backup: &BackupToml,
+    live: &[ListEntry],
+    target: Service,
+    scores: &ScoreConversion,
+) -> Plan {
+    let list_type = backup.user_section.list_type;
+    let live_entries: HashMap<u32, &ListEntry> = live
+        .iter()
+        .filter_map(|entry| Some((entry.id(target)?, entry)))
+        .collect();
+
+    let mut changes = Vec::new();
+    let mut without_mal_id = Vec::new();
+    for backup_entry in backup.entries() {
+        let id = match target {
+            Service::Anilist => backup_entry.id,
+            Service::Mal => match backup_entry.id_mal {
+                Some(id_mal) => id_mal,
+                None => {
+                    without_mal_id.push(backup_entry);
+                    continue;
+                }
+            },
+        };
+        let live_values = live_entries.get(&id).map(|live_entry| match target {
+            Service::Anilist => live_entry.anilist_values(),
+            Service::Mal => live_entry.values(),
+        });
+        let mut after = backup_values(backup_entry, backup.schema_version, live_values.clone());
+        if let Some(score_format) = backup.user_section.score_format {
+            after.score = scores.from_format(after.score, score_format);
+        }
+        // MAL doesn't have the fields only AniList has
+        if target == Service::Mal {
+            after = EntryValues {
+                private: None,
+                hidden_from_status_lists: None,
+                custom_lists: None,
+                advanced_scores: None,
+                ..after
+            };
+        }
+        let before = match live_values {
+            Some(before) => {
+                let unchanged = match target {
+                    Service::Anilist => before == after,
+                    Service::Mal => !sync::differs(&after, &before, scores),
+                };
+                if unchanged {
+                    continue;
+                }
+                Some(before)
+            }
+            None => None,
+        };
+        changes.push(Change {
+            action: match before {
+                Some(_) => Action::Update,
+                None => Action::Create,
+            },
+            target,
+            title: backup_entry.title.clone(),
+            anilist_id: backup_entry.id,
+            mal_id: backup_entry.id_mal,
+            before,
+            after,
+        });
+    }
+
+    if !without_mal_id.is_empty() {
+        eprintln!(
+            "{} entries don't have a MAL id and can't be restored to MAL:",
+            without_mal_id.len()
+        );
+        for entry in without_mal_id {
+            eprintln!("  {} (AniList id {})", entry.title, entry.id);
+        }
+    }
+    changes.iter().for_each(sync::check_notes_length);
+
+    Plan { list_type, changes }
+}
+
+/// The first schema version with more than the status, score and progress of each entry
+const FULL_ENTRIES_VERSION: u32 = 2;
+
+/// Backups from before `FULL_ENTRIES_VERSION` only have the status, score and progress,
+/// so everything else is kept as it is on `live` rather than being cleared
+fn backup_values(
+    entry: &EntrySection,
+    schema_version: u32,
+    live: Option<EntryValues>,
+) -> EntryValues {
+    match live {
+        Some(live) if schema_version < FULL_ENTRIES_VERSION => EntryValues {
+            status: entry.status,
+            score: entry.score,
+            progress: entry.progress,
+            mal_score: None,
+            ..live
+        },
+        _ => EntryValues {
+            status: entry.status,
+            score: entry.score,
+            mal_score: None,
+            progress: entry.progress,
+            progress_volumes: entry.progress_volumes,
+            repeat: entry.repeat,
+            started_at: entry.started_at.unwrap_or_default(),
+            completed_at: entry.completed_at.unwrap_or_default(),
+            notes: entry.notes.clone(),
+            private: Some(entry.private),
+            hidden_from_status_lists: Some(entry.hidden_from_status_lists),
+            custom_lists: Some(entry.custom_lists.clone()),
+            advanced_scores: Some(entry.advanced_scores.clone()),
+        },
+    }
+}
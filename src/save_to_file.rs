@@ -1,190 +1,406 @@
+use std::collections::BTreeMap;
 use std::fs;
 use std::io::prelude::*;
+use std::path::{Path, PathBuf};
 
-use home;
 use serde::{Deserialize, Serialize};
 
-use super::anilist_queries::{Entry, Lists, MediaFormat, MediaListStatus, MediaType, UserData};
+use super::anilist_queries::{
+    Entries, FuzzyDate, MediaFormat, MediaListStatus, MediaType, ScoreFormat,
+};
+use super::error::{Error, Result};
+use super::provider::{ListEntry, User};
+use super::scores::ScoreConversion;
 
 #[derive(Deserialize, Serialize, Debug)]
-struct UserSection {
-    user_id: u32,
-    username: String,
-    list_type: MediaType,
-    total_anime: u32,
-    watching: u32,
-    completed: u32,
-    on_hold: u32,
-    dropped: u32,
-    planning: u32,
-    rewatching: u32,
+pub struct UserSection {
+    pub user_id: u32,
+    pub username: String,
+    pub list_type: MediaType,
+    pub total_anime: u32,
+    pub watching: u32,
+    pub completed: u32,
+    pub on_hold: u32,
+    pub dropped: u32,
+    pub planning: u32,
+    pub rewatching: u32,
+    /// The format the scores are in. Backups from before schema version 4 don't have it
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub score_format: Option<ScoreFormat>,
 }
 
 impl UserSection {
-    fn new(lists: &Lists, user: UserData, list_type: MediaType) -> UserSection {
-        let watching_position = lists
-            .lists
-            .iter()
-            .position(|x| x.entries[0].status == MediaListStatus::Current);
-        let watching_len = match watching_position {
-            None => 0,
-            Some(watching_position) => lists.lists[watching_position].entries.len() as u32,
-        };
-
-        let completed_position = lists
-            .lists
-            .iter()
-            .position(|x| x.entries[0].status == MediaListStatus::Completed);
-        let completed_len = match completed_position {
-            None => 0,
-            Some(completed_position) => lists.lists[completed_position].entries.len() as u32,
-        };
-
-        let paused_position = lists
-            .lists
-            .iter()
-            .position(|x| x.entries[0].status == MediaListStatus::Paused);
-        let paused_len = match paused_position {
-            None => 0,
-            Some(paused_position) => lists.lists[paused_position].entries.len() as u32,
-        };
-
-        let dropped_position = lists
-            .lists
-            .iter()
-            .position(|x| x.entries[0].status == MediaListStatus::Dropped);
-        let dropped_len = match dropped_position {
-            None => 0,
-            Some(dropped_position) => lists.lists[dropped_position].entries.len() as u32,
+    fn new(
+        entries: &[ListEntry],
+        user: &User,
+        list_type: MediaType,
+        score_format: ScoreFormat,
+    ) -> UserSection {
+        let count = |status: MediaListStatus| {
+            entries
+                .iter()
+                .filter(|entry| entry.status == status)
+                .count() as u32
         };
 
-        let planning_position = lists
-            .lists
-            .iter()
-            .position(|x| x.entries[0].status == MediaListStatus::Planning);
-        let planning_len = match planning_position {
-            None => 0,
-            Some(planning_position) => lists.lists[planning_position].entries.len() as u32,
-        };
-
-        let repeating_position = lists
-            .lists
-            .iter()
-            .position(|x| x.entries[0].status == MediaListStatus::Repeating);
-        let repeating_len = match repeating_position {
-            None => 0,
-            Some(repeating_position) => lists.lists[repeating_position].entries.len() as u32,
-        };
-
-        let total =
-            watching_len + completed_len + paused_len + dropped_len + planning_len + repeating_len;
-
         UserSection {
             user_id: user.id,
-            username: user.name,
+            username: user.name.clone(),
             list_type,
-            total_anime: total,
-            watching: watching_len,
-            completed: completed_len,
-            on_hold: paused_len,
-            dropped: dropped_len,
-            planning: planning_len,
-            rewatching: repeating_len,
+            total_anime: entries.len() as u32,
+            watching: count(MediaListStatus::Current),
+            completed: count(MediaListStatus::Completed),
+            on_hold: count(MediaListStatus::Paused),
+            dropped: count(MediaListStatus::Dropped),
+            planning: count(MediaListStatus::Planning),
+            rewatching: count(MediaListStatus::Repeating),
+            score_format: Some(score_format),
         }
     }
 }
 
+// everything added after schema version 1 defaults so older backups can still be read.
+// The tables (dates and advanced scores) have to come last for TOML
 #[derive(Deserialize, Serialize, Debug)]
-struct EntrySection {
-    title: String,
-    id: u32,
-    id_mal: Option<u32>,
-    episodes: Option<u32>,
-    chapters: Option<u32>,
-    format: MediaFormat,
-    status: MediaListStatus,
-    score: f32,
-    progress: u32,
+pub struct EntrySection {
+    pub title: String,
+    pub id: u32,
+    pub id_mal: Option<u32>,
+    pub episodes: Option<u32>,
+    pub chapters: Option<u32>,
+    pub format: MediaFormat,
+    pub status: MediaListStatus,
+    pub score: f32,
+    pub progress: u32,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub progress_volumes: Option<u32>,
+    #[serde(default)]
+    pub repeat: u32,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub notes: Option<String>,
+    #[serde(default)]
+    pub private: bool,
+    #[serde(default)]
+    pub hidden_from_status_lists: bool,
+    /// The names of the custom lists it's in
+    #[serde(default)]
+    pub custom_lists: Vec<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub created_at: Option<i64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub updated_at: Option<i64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub started_at: Option<FuzzyDate>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub completed_at: Option<FuzzyDate>,
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub advanced_scores: BTreeMap<String, f32>,
 }
 
 impl EntrySection {
-    fn new(entry: &Entry) -> EntrySection {
-        EntrySection {
-            title: entry.media.title.user_preferred.clone(),
-            id: entry.media.id,
-            id_mal: entry.media.id_mal,
-            episodes: entry.media.episodes,
-            chapters: entry.media.chapters,
-            format: entry.media.format,
+    /// `None` for entries that aren't from AniList, because they don't have everything a backup needs
+    fn new(entry: &ListEntry) -> Option<EntrySection> {
+        let date = |date: FuzzyDate| if date.is_empty() { None } else { Some(date) };
+        Some(EntrySection {
+            title: entry.title.clone(),
+            id: entry.anilist_id?,
+            id_mal: entry.mal_id,
+            episodes: entry.episodes,
+            chapters: entry.chapters,
+            format: entry.format?,
             status: entry.status,
             score: entry.score,
             progress: entry.progress,
-        }
+            progress_volumes: entry.progress_volumes,
+            repeat: entry.repeat,
+            notes: entry.notes.clone(),
+            private: entry.private,
+            hidden_from_status_lists: entry.hidden_from_status_lists,
+            custom_lists: entry.custom_lists.clone(),
+            created_at: entry.created_at,
+            updated_at: entry.updated_at,
+            started_at: date(entry.started_at),
+            completed_at: date(entry.completed_at),
+            advanced_scores: entry.advanced_scores.clone(),
+        })
     }
 }
 
-#[derive(Deserialize, Serialize, Debug)]
-struct Current(Option<Vec<EntrySection>>);
+#[derive(Deserialize, Serialize, Debug, Default)]
+pub struct Current(pub Option<Vec<EntrySection>>);
 
-#[derive(Deserialize, Serialize, Debug)]
-struct Completed(Option<Vec<EntrySection>>);
+#[derive(Deserialize, Serialize, Debug, Default)]
+pub struct Completed(pub Option<Vec<EntrySection>>);
 
-#[derive(Deserialize, Serialize, Debug)]
-struct Planning(Option<Vec<EntrySection>>);
+#[derive(Deserialize, Serialize, Debug, Default)]
+pub struct Planning(pub Option<Vec<EntrySection>>);
 
-#[derive(Deserialize, Serialize, Debug)]
-struct Dropped(Option<Vec<EntrySection>>);
+#[derive(Deserialize, Serialize, Debug, Default)]
+pub struct Dropped(pub Option<Vec<EntrySection>>);
 
-#[derive(Deserialize, Serialize, Debug)]
-struct Paused(Option<Vec<EntrySection>>);
+#[derive(Deserialize, Serialize, Debug, Default)]
+pub struct Paused(pub Option<Vec<EntrySection>>);
+
+#[derive(Deserialize, Serialize, Debug, Default)]
+pub struct Repeating(pub Option<Vec<EntrySection>>);
 
+/// One of the lists on AniList. The entries themselves are in the status sections,
+/// so this only has their AniList ids
 #[derive(Deserialize, Serialize, Debug)]
-struct Repeating(Option<Vec<EntrySection>>);
+pub struct ListSection {
+    pub name: String,
+    pub is_custom_list: bool,
+    pub is_split_completed_list: bool,
+    /// `None` for custom lists
+    pub status: Option<MediaListStatus>,
+    pub entries: Vec<u32>,
+}
+
+impl ListSection {
+    pub fn new(list: &Entries) -> ListSection {
+        ListSection {
+            name: list.name.clone(),
+            is_custom_list: list.is_custom_list,
+            is_split_completed_list: list.is_split_completed_list,
+            status: list.status,
+            entries: list.entries.iter().map(|entry| entry.media.id).collect(),
+        }
+    }
+}
+
+/// Bumped whenever the layout of a backup changes in a way that tools reading it need to know about.
+/// See the README for what each version contains
+pub const SCHEMA_VERSION: u32 = 4;
 
+// empty sections aren't written, so they have to default when reading
 #[derive(Deserialize, Serialize, Debug)]
-struct BackupToml {
-    user_section: UserSection,
-    repeating: Repeating,
-    current: Current,
-    completed: Completed,
-    paused: Paused,
-    dropped: Dropped,
-    planning: Planning,
-}
-
-pub fn write_list_to_file(list: &Lists, user: (u32, &str), list_type: MediaType) {
-    let user = UserData {
-        id: user.0,
-        name: user.1.to_string(),
+pub struct BackupToml {
+    /// Backups from before this existed don't have it, so they count as version 0
+    #[serde(default)]
+    pub schema_version: u32,
+    pub user_section: UserSection,
+    #[serde(default)]
+    pub repeating: Repeating,
+    #[serde(default)]
+    pub current: Current,
+    #[serde(default)]
+    pub completed: Completed,
+    #[serde(default)]
+    pub paused: Paused,
+    #[serde(default)]
+    pub dropped: Dropped,
+    #[serde(default)]
+    pub planning: Planning,
+    /// Left out when empty, because TOML can't have a plain value after the tables
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub lists: Vec<ListSection>,
+}
+
+impl BackupToml {
+    /// Every entry, in the order the sections are written
+    pub fn entries(&self) -> impl Iterator<Item = &EntrySection> {
+        let sections = [
+            &self.repeating.0,
+            &self.current.0,
+            &self.completed.0,
+            &self.paused.0,
+            &self.dropped.0,
+            &self.planning.0,
+        ];
+        IntoIterator::into_iter(sections)
+            .filter_map(|section| section.as_ref())
+            .flatten()
+    }
+}
+
+/// Reads a TOML or JSON backup, going by the file extension
+pub fn read_backup(path: &Path) -> Result<BackupToml> {
+    let contents = fs::read_to_string(path).map_err(Error::io(path))?;
+    let invalid = |error: &dyn std::fmt::Display| {
+        Error::Input(format!("Couldn't parse {}: {}", path.display(), error))
+    };
+    let backup: BackupToml = match path.extension().and_then(|e| e.to_str()) {
+        Some("toml") => toml::from_str(&contents).map_err(|error| invalid(&error))?,
+        Some("json") => serde_json::from_str(&contents).map_err(|error| invalid(&error))?,
+        _ => {
+            return Err(Error::Input(
+                "Only TOML and JSON backups can be read back in".to_string(),
+            ))
+        }
     };
+    if backup.schema_version > SCHEMA_VERSION {
+        return Err(Error::Input(format!(
+            "{} was written by a newer version of list-backup (schema version {})",
+            path.display(),
+            backup.schema_version
+        )));
+    }
+    Ok(backup)
+}
 
-    let mut file_path = home::home_dir().unwrap();
-    file_path.push("Documents");
-    if list_type == MediaType::ANIME {
-        file_path.push("anime-backup");
-    } else {
-        file_path.push("manga-backup");
-    }
-    file_path.set_extension("toml");
-
-    // create the file
-    let file = fs::OpenOptions::new()
-        .read(true)
-        .write(true)
-        .create(true)
-        .truncate(true)
-        .open(file_path)
-        .unwrap();
-
-    let user_section = UserSection::new(list, user, list_type);
-
-    let current_list = Current(create_entry_section_vec(&list, MediaListStatus::Current));
-    let completed_list = Completed(create_entry_section_vec(&list, MediaListStatus::Completed));
-    let planning_list = Planning(create_entry_section_vec(&list, MediaListStatus::Planning));
-    let dropped_list = Dropped(create_entry_section_vec(&list, MediaListStatus::Dropped));
-    let paused_list = Paused(create_entry_section_vec(&list, MediaListStatus::Paused));
-    let repeating_list = Repeating(create_entry_section_vec(&list, MediaListStatus::Repeating));
+#[derive(Debug, Clone, PartialEq)]
+pub enum Format {
+    Toml,
+    Json {
+        compact: bool,
+    },
+    /// The format MAL exports lists in, which its importer also accepts
+    MalXml {
+        scores: ScoreConversion,
+    },
+    /// One row per entry with the chosen columns
+    Csv {
+        columns: Vec<Column>,
+    },
+}
+
+impl Format {
+    /// `columns` is a comma separated list of column names, only used for CSV.
+    /// `scores` is only used for MAL's format
+    pub fn from_arg(
+        format: &str,
+        compact: bool,
+        columns: Option<&str>,
+        scores: ScoreConversion,
+    ) -> Result<Format> {
+        Ok(match format {
+            "toml" => Format::Toml,
+            "json" => Format::Json { compact },
+            "mal-xml" => Format::MalXml { scores },
+            "csv" => Format::Csv {
+                columns: match columns {
+                    Some(columns) => Column::parse_list(columns)?,
+                    None => Column::ALL.to_vec(),
+                },
+            },
+            _ => {
+                return Err(Error::Input(
+                    "The value for 'format' needs to be 'toml', 'json', 'mal-xml' or 'csv'"
+                        .to_string(),
+                ))
+            }
+        })
+    }
+
+    pub fn extension(&self) -> &'static str {
+        match self {
+            Format::Toml => "toml",
+            Format::Json { .. } => "json",
+            Format::MalXml { .. } => "xml",
+            Format::Csv { .. } => "csv",
+        }
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum Column {
+    Title,
+    Id,
+    IdMal,
+    Format,
+    Status,
+    Score,
+    Progress,
+    Episodes,
+    Chapters,
+}
+
+impl Column {
+    pub const ALL: [Column; 9] = [
+        Column::Title,
+        Column::Id,
+        Column::IdMal,
+        Column::Format,
+        Column::Status,
+        Column::Score,
+        Column::Progress,
+        Column::Episodes,
+        Column::Chapters,
+    ];
+
+    /// The name used in `--columns` and the header row
+    pub fn name(&self) -> &'static str {
+        match self {
+            Column::Title => "title",
+            Column::Id => "id",
+            Column::IdMal => "id_mal",
+            Column::Format => "format",
+            Column::Status => "status",
+            Column::Score => "score",
+            Column::Progress => "progress",
+            Column::Episodes => "episodes",
+            Column::Chapters => "chapters",
+        }
+    }
+
+    fn parse_list(columns: &str) -> Result<Vec<Column>> {
+        columns
+            .split(',')
+            .map(|name| {
+                let name = name.trim();
+                match Column::ALL.iter().find(|column| column.name() == name) {
+                    Some(column) => Ok(*column),
+                    None => {
+                        let names: Vec<&str> = Column::ALL.iter().map(|c| c.name()).collect();
+                        Err(Error::Input(format!(
+                            "Unknown column '{}'. The columns are: {}",
+                            name,
+                            names.join(", ")
+                        )))
+                    }
+                }
+            })
+            .collect()
+    }
+
+    fn value(&self, entry: &EntrySection) -> String {
+        let optional = |value: Option<u32>| value.map(|v| v.to_string()).unwrap_or_default();
+        match self {
+            Column::Title => entry.title.clone(),
+            Column::Id => entry.id.to_string(),
+            Column::IdMal => optional(entry.id_mal),
+            Column::Format => format!("{:?}", entry.format),
+            Column::Status => format!("{:?}", entry.status),
+            Column::Score => entry.score.to_string(),
+            Column::Progress => entry.progress.to_string(),
+            Column::Episodes => optional(entry.episodes),
+            Column::Chapters => optional(entry.chapters),
+        }
+    }
+}
+
+pub enum Output {
+    Stdout,
+    File(PathBuf),
+}
+
+/// Writes the list to `output`, creating any missing parent directories.
+/// `lists` are the AniList lists the entries are in, and `score_format` the format of their scores
+pub fn write_list_to_file(
+    entries: &[ListEntry],
+    lists: Vec<ListSection>,
+    user: &User,
+    list_type: MediaType,
+    score_format: ScoreFormat,
+    format: &Format,
+    output: &Output,
+) -> Result<()> {
+    let user_section = UserSection::new(entries, user, list_type, score_format);
+
+    let current_list = Current(create_entry_section_vec(entries, MediaListStatus::Current));
+    let completed_list = Completed(create_entry_section_vec(
+        entries,
+        MediaListStatus::Completed,
+    ));
+    let planning_list = Planning(create_entry_section_vec(entries, MediaListStatus::Planning));
+    let dropped_list = Dropped(create_entry_section_vec(entries, MediaListStatus::Dropped));
+    let paused_list = Paused(create_entry_section_vec(entries, MediaListStatus::Paused));
+    let repeating_list = Repeating(create_entry_section_vec(
+        entries,
+        MediaListStatus::Repeating,
+    ));
     let backup = BackupToml {
+        schema_version: SCHEMA_VERSION,
         user_section,
         current: current_list,
         repeating: repeating_list,
@@ -192,29 +408,262 @@ pub fn write_list_to_file(list: &Lists, user: (u32, &str), list_type: MediaType)
         paused: paused_list,
         dropped: dropped_list,
         planning: planning_list,
+        lists,
+    };
+    let contents = match format {
+        Format::Toml => toml::to_string(&backup).map_err(|error| {
+            Error::Input(format!("Couldn't write the backup as TOML: {}", error))
+        })?,
+        Format::Json { compact: true } => serde_json::to_string(&backup).unwrap(),
+        Format::Json { compact: false } => serde_json::to_string_pretty(&backup).unwrap(),
+        Format::MalXml { scores } => to_mal_xml(&backup, scores),
+        Format::Csv { columns } => to_csv(&backup, columns),
     };
-    let backup_toml = toml::to_string(&backup).unwrap();
-    writeln!(&file, "{}", backup_toml).unwrap();
+
+    // CSV rows already end in `\r\n`, and another line ending would be an empty record
+    let newline = match format {
+        Format::Csv { .. } => "",
+        _ => "\n",
+    };
+    match output {
+        Output::Stdout => print!("{}{}", contents, newline),
+        Output::File(file_path) => {
+            if let Some(parent) = file_path.parent() {
+                fs::create_dir_all(parent).map_err(Error::io(parent))?;
+            }
+            let file = fs::OpenOptions::new()
+                .write(true)
+                .create(true)
+                .truncate(true)
+                .open(file_path)
+                .map_err(Error::io(file_path))?;
+            write!(&file, "{}{}", contents, newline).map_err(Error::io(file_path))?;
+        }
+    }
+    Ok(())
 }
 
-fn create_entry_section_vec(list: &Lists, status: MediaListStatus) -> Option<Vec<EntrySection>> {
-    let list_pos = list
-        .lists
+fn create_entry_section_vec(
+    entries: &[ListEntry],
+    status: MediaListStatus,
+) -> Option<Vec<EntrySection>> {
+    let vec: Vec<EntrySection> = entries
         .iter()
-        .position(|x| x.entries[0].status == status)
-        .unwrap_or_else(|| 999);
+        .filter(|entry| entry.status == status)
+        .filter_map(EntrySection::new)
+        .collect();
 
-    let mut vec = Vec::new();
+    if vec.is_empty() {
+        None
+    } else {
+        Some(vec)
+    }
+}
 
-    if list_pos <= list.lists.len() {
-        let list = &list.lists[list_pos].entries;
+/// Writes the backup in MAL's export format. Entries without a MAL id can't be imported so they're left out
+fn to_mal_xml(backup: &BackupToml, scores: &ScoreConversion) -> String {
+    let user = &backup.user_section;
+    let mut xml = String::new();
+    xml.push_str("<?xml version=\"1.0\" encoding=\"UTF-8\" ?>\n");
+    xml.push_str("<myanimelist>\n");
 
-        for entry in list.iter() {
-            let entry_section = EntrySection::new(&entry);
-            vec.push(entry_section);
+    xml.push_str("\t<myinfo>\n");
+    xml_tag(&mut xml, "user_name", xml_escape(&user.username));
+    match user.list_type {
+        MediaType::ANIME => {
+            xml_tag(&mut xml, "user_export_type", 1);
+            xml_tag(&mut xml, "user_total_anime", user.total_anime);
+            xml_tag(
+                &mut xml,
+                "user_total_watching",
+                user.watching + user.rewatching,
+            );
+            xml_tag(&mut xml, "user_total_completed", user.completed);
+            xml_tag(&mut xml, "user_total_onhold", user.on_hold);
+            xml_tag(&mut xml, "user_total_dropped", user.dropped);
+            xml_tag(&mut xml, "user_total_plantowatch", user.planning);
         }
-        Some(vec)
+        MediaType::MANGA => {
+            xml_tag(&mut xml, "user_export_type", 2);
+            xml_tag(&mut xml, "user_total_manga", user.total_anime);
+            xml_tag(
+                &mut xml,
+                "user_total_reading",
+                user.watching + user.rewatching,
+            );
+            xml_tag(&mut xml, "user_total_completed", user.completed);
+            xml_tag(&mut xml, "user_total_onhold", user.on_hold);
+            xml_tag(&mut xml, "user_total_dropped", user.dropped);
+            xml_tag(&mut xml, "user_total_plantoread", user.planning);
+        }
+    }
+    xml.push_str("\t</myinfo>\n");
+
+    let mut skipped = 0;
+    for entry in backup.entries() {
+        let id_mal = match entry.id_mal {
+            Some(id_mal) => id_mal,
+            None => {
+                skipped += 1;
+                continue;
+            }
+        };
+        let title = cdata(&entry.title);
+        let score = scores.to_mal(entry.score);
+        let status = mal_xml_status(entry.status, user.list_type);
+        let repeating = (entry.status == MediaListStatus::Repeating) as u8;
+        let start_date = mal_xml_date(entry.started_at);
+        let finish_date = mal_xml_date(entry.completed_at);
+        let comments = cdata(entry.notes.as_deref().unwrap_or(""));
+
+        match user.list_type {
+            MediaType::ANIME => {
+                xml.push_str("\t<anime>\n");
+                xml_tag(&mut xml, "series_animedb_id", id_mal);
+                xml_tag(&mut xml, "series_title", &title);
+                xml_tag(&mut xml, "series_type", mal_xml_type(entry.format));
+                xml_tag(&mut xml, "series_episodes", entry.episodes.unwrap_or(0));
+                xml_tag(&mut xml, "my_id", 0);
+                xml_tag(&mut xml, "my_watched_episodes", entry.progress);
+                xml_tag(&mut xml, "my_start_date", &start_date);
+                xml_tag(&mut xml, "my_finish_date", &finish_date);
+                xml_tag(&mut xml, "my_rated", "");
+                xml_tag(&mut xml, "my_score", score);
+                xml_tag(&mut xml, "my_storage", "");
+                xml_tag(&mut xml, "my_storage_value", "0.00");
+                xml_tag(&mut xml, "my_status", status);
+                xml_tag(&mut xml, "my_comments", &comments);
+                xml_tag(&mut xml, "my_times_watched", entry.repeat);
+                xml_tag(&mut xml, "my_rewatch_value", "");
+                xml_tag(&mut xml, "my_priority", "LOW");
+                xml_tag(&mut xml, "my_tags", cdata(""));
+                xml_tag(&mut xml, "my_rewatching", repeating);
+                xml_tag(&mut xml, "my_rewatching_ep", 0);
+                xml_tag(&mut xml, "my_discuss", 1);
+                xml_tag(&mut xml, "my_sns", "default");
+                xml_tag(&mut xml, "update_on_import", 1);
+                xml.push_str("\t</anime>\n");
+            }
+            MediaType::MANGA => {
+                xml.push_str("\t<manga>\n");
+                xml_tag(&mut xml, "manga_mangadb_id", id_mal);
+                xml_tag(&mut xml, "manga_title", &title);
+                xml_tag(&mut xml, "manga_volumes", 0);
+                xml_tag(&mut xml, "manga_chapters", entry.chapters.unwrap_or(0));
+                xml_tag(&mut xml, "my_id", 0);
+                xml_tag(
+                    &mut xml,
+                    "my_read_volumes",
+                    entry.progress_volumes.unwrap_or(0),
+                );
+                xml_tag(&mut xml, "my_read_chapters", entry.progress);
+                xml_tag(&mut xml, "my_start_date", &start_date);
+                xml_tag(&mut xml, "my_finish_date", &finish_date);
+                xml_tag(&mut xml, "my_scanalation_group", cdata(""));
+                xml_tag(&mut xml, "my_score", score);
+                xml_tag(&mut xml, "my_storage", "");
+                xml_tag(&mut xml, "my_retail_volumes", 0);
+                xml_tag(&mut xml, "my_status", status);
+                xml_tag(&mut xml, "my_comments", &comments);
+                xml_tag(&mut xml, "my_times_read", entry.repeat);
+                xml_tag(&mut xml, "my_tags", cdata(""));
+                xml_tag(&mut xml, "my_priority", "Low");
+                xml_tag(&mut xml, "my_reread_value", "");
+                xml_tag(&mut xml, "my_rereading", repeating);
+                xml_tag(&mut xml, "my_discuss", 1);
+                xml_tag(&mut xml, "my_sns", "default");
+                xml_tag(&mut xml, "update_on_import", 1);
+                xml.push_str("\t</manga>\n");
+            }
+        }
+    }
+    xml.push_str("</myanimelist>");
+
+    if skipped > 0 {
+        eprintln!("Left out {} entries that don't have a MAL id", skipped);
+    }
+    xml
+}
+
+/// Writes every entry as a row, following RFC 4180
+fn to_csv(backup: &BackupToml, columns: &[Column]) -> String {
+    let mut csv = String::new();
+    let header: Vec<String> = columns.iter().map(|c| csv_field(c.name())).collect();
+    csv.push_str(&header.join(","));
+    csv.push_str("\r\n");
+
+    for entry in backup.entries() {
+        let row: Vec<String> = columns.iter().map(|c| csv_field(&c.value(entry))).collect();
+        csv.push_str(&row.join(","));
+        csv.push_str("\r\n");
+    }
+    csv
+}
+
+/// Quotes a field if it has a comma, quote or line break in it, doubling any quotes
+fn csv_field(value: &str) -> String {
+    if value.contains(&[',', '"', '\r', '\n'][..]) {
+        format!("\"{}\"", value.replace('"', "\"\""))
     } else {
-        None
+        value.to_string()
+    }
+}
+
+fn xml_tag<T: std::fmt::Display>(xml: &mut String, name: &str, value: T) {
+    xml.push_str(&format!("\t\t<{0}>{1}</{0}>\n", name, value));
+}
+
+fn xml_escape(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+        .replace('\'', "&apos;")
+}
+
+/// Wraps text in a CDATA section. A `]]>` in the text would end it early, so it's split across two sections
+fn cdata(text: &str) -> String {
+    format!("<![CDATA[{}]]>", text.replace("]]>", "]]]]><![CDATA[>"))
+}
+
+/// MAL writes unknown parts of a date as zeros
+fn mal_xml_date(date: Option<FuzzyDate>) -> String {
+    let date = date.unwrap_or_default();
+    format!(
+        "{:04}-{:02}-{:02}",
+        date.year.unwrap_or(0),
+        date.month.unwrap_or(0),
+        date.day.unwrap_or(0)
+    )
+}
+
+fn mal_xml_status(status: MediaListStatus, list_type: MediaType) -> &'static str {
+    match status {
+        MediaListStatus::Current => match list_type {
+            MediaType::ANIME => "Watching",
+            MediaType::MANGA => "Reading",
+        },
+        // rewatching is a separate flag on MAL
+        MediaListStatus::Completed | MediaListStatus::Repeating => "Completed",
+        MediaListStatus::Paused => "On-Hold",
+        MediaListStatus::Dropped => "Dropped",
+        MediaListStatus::Planning => match list_type {
+            MediaType::ANIME => "Plan to Watch",
+            MediaType::MANGA => "Plan to Read",
+        },
+    }
+}
+
+fn mal_xml_type(format: MediaFormat) -> &'static str {
+    match format {
+        MediaFormat::Tv | MediaFormat::TvShort => "TV",
+        MediaFormat::Movie => "Movie",
+        MediaFormat::Special => "Special",
+        MediaFormat::Ova => "OVA",
+        MediaFormat::Ona => "ONA",
+        MediaFormat::Music => "Music",
+        MediaFormat::Manga => "Manga",
+        MediaFormat::Novel => "Novel",
+        MediaFormat::OneShot => "One-shot",
     }
 }
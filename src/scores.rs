@@ -0,0 +1,106 @@
+use serde::{Deserialize, Serialize};
+
+use super::anilist_queries::ScoreFormat;
+
+/// What to do with scores that fall between two of the other format's scores
+#[derive(Deserialize, Serialize, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum Rounding {
+    Down,
+    Nearest,
+    Up,
+}
+
+/// Rounding down is what was always done before it could be changed
+impl Default for Rounding {
+    fn default() -> Rounding {
+        Rounding::Down
+    }
+}
+
+/// Converts scores between the user's AniList score format and MAL's whole numbers from 0 to 10.
+/// 0 means no score on both, so it's always kept as 0, and no other score is turned into 0
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub struct ScoreConversion {
+    pub format: ScoreFormat,
+    pub rounding: Rounding,
+}
+
+impl ScoreConversion {
+    pub fn to_mal(&self, score: f32) -> u8 {
+        if score <= 0.0 {
+            return 0;
+        }
+        divide(out_of_100(self.format, score), 10, self.rounding).clamp(1, 10) as u8
+    }
+
+    pub fn from_mal(&self, score: u8) -> f32 {
+        if score == 0 {
+            return 0.0;
+        }
+        let score = score as i32;
+        let converted = match self.format {
+            ScoreFormat::Point100 => score * 10,
+            ScoreFormat::Point10Decimal | ScoreFormat::Point10 => score,
+            ScoreFormat::Point5 => divide(score * 10, 20, self.rounding).clamp(1, 5),
+            // so every smiley comes back as itself
+            ScoreFormat::Point3 => {
+                self.closest_smiley(score, |smiley| self.to_mal(smiley as f32) as i32)
+            }
+        };
+        converted as f32
+    }
+
+    /// Converts a score from another AniList score format to this one,
+    /// e.g. for a backup written before the user changed their format
+    pub fn from_format(&self, score: f32, format: ScoreFormat) -> f32 {
+        if score <= 0.0 || format == self.format {
+            return score;
+        }
+        let score = out_of_100(format, score);
+        let converted = match self.format {
+            ScoreFormat::Point100 => score.clamp(1, 100),
+            ScoreFormat::Point10Decimal => return score.clamp(1, 100) as f32 / 10.0,
+            ScoreFormat::Point10 => divide(score, 10, self.rounding).clamp(1, 10),
+            ScoreFormat::Point5 => divide(score, 20, self.rounding).clamp(1, 5),
+            ScoreFormat::Point3 => self.closest_smiley(score, |smiley| {
+                out_of_100(ScoreFormat::Point3, smiley as f32)
+            }),
+        };
+        converted as f32
+    }
+
+    /// The smiley whose score in the other format, from `smiley_score`, is closest to `score`.
+    /// Halfway between two smileys only goes down when rounding down
+    fn closest_smiley(&self, score: i32, smiley_score: impl Fn(i32) -> i32) -> i32 {
+        (1..=3)
+            .min_by_key(|smiley| {
+                let distance = (smiley_score(*smiley) - score).abs();
+                let tie_break = match self.rounding {
+                    Rounding::Down => *smiley,
+                    Rounding::Nearest | Rounding::Up => -smiley,
+                };
+                (distance, tie_break)
+            })
+            .unwrap()
+    }
+}
+
+/// Scores are compared out of 100 so the maths can be done on whole numbers
+fn out_of_100(format: ScoreFormat, score: f32) -> i32 {
+    match format {
+        ScoreFormat::Point100 => score.round() as i32,
+        ScoreFormat::Point10Decimal | ScoreFormat::Point10 => (score * 10.0).round() as i32,
+        ScoreFormat::Point5 => score.round() as i32 * 20,
+        // the same as AniList uses when showing a smiley to someone with another format
+        ScoreFormat::Point3 => 35 + (score.round() as i32 - 1) * 25,
+    }
+}
+
+fn divide(dividend: i32, divisor: i32, rounding: Rounding) -> i32 {
+    match rounding {
+        Rounding::Down => dividend.div_euclid(divisor),
+        Rounding::Nearest => (dividend * 2 + divisor).div_euclid(divisor * 2),
+        Rounding::Up => -(-dividend).div_euclid(divisor),
+    }
+}
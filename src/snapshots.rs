@@ -0,0 +1,208 @@
+use std::cmp::Reverse;
+use std::collections::HashSet;
+use std::env;
+use std::ffi::OsStr;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use chrono::{DateTime, Datelike, NaiveDateTime, TimeZone, Utc};
+
+use super::anilist_queries::MediaType;
+use super::error::{Error, Result};
+
+const TIMESTAMP_FORMAT: &str = "%Y-%m-%dT%H%M%SZ";
+/// The formats that are kept as snapshots
+const EXTENSIONS: [&str; 4] = ["toml", "json", "xml", "csv"];
+
+/// A backup file written by `backup`
+#[derive(Debug)]
+pub struct Snapshot {
+    pub path: PathBuf,
+    pub list_type: MediaType,
+    pub taken_at: DateTime<Utc>,
+    /// Tells apart snapshots taken in the same second. The first one is 0
+    pub version: u32,
+}
+
+/// How many snapshots to keep. Anything kept by at least one rule is kept
+#[derive(Debug, Default)]
+pub struct Retention {
+    /// The newest n snapshots
+    pub keep_last: Option<usize>,
+    /// The newest snapshot of each of the last n days that have one
+    pub keep_daily: Option<usize>,
+    pub keep_weekly: Option<usize>,
+    pub keep_monthly: Option<usize>,
+}
+
+impl Retention {
+    pub fn is_empty(&self) -> bool {
+        self.keep_last.is_none()
+            && self.keep_daily.is_none()
+            && self.keep_weekly.is_none()
+            && self.keep_monthly.is_none()
+    }
+}
+
+/// Where snapshots are saved. Uses the configured directory if there is one,
+/// then `~/Documents/list-backup` if `~/Documents` exists, then the XDG data directory
+pub fn snapshot_dir(configured: Option<&Path>) -> Result<PathBuf> {
+    if let Some(dir) = configured {
+        return Ok(dir.to_path_buf());
+    }
+
+    let home = home::home_dir();
+    if let Some(home) = &home {
+        let documents = home.join("Documents");
+        if documents.is_dir() {
+            return Ok(documents.join("list-backup"));
+        }
+    }
+
+    let data_dir = match env::var_os("XDG_DATA_HOME") {
+        Some(dir) if !dir.is_empty() => PathBuf::from(dir),
+        _ => match home {
+            Some(home) => home.join(".local").join("share"),
+            None => return Err(Error::Config("Couldn't find a directory to save backups in. Use --output or set one in the config file".to_string())),
+        },
+    };
+    Ok(data_dir.join("list-backup"))
+}
+
+fn prefix(list_type: MediaType) -> &'static str {
+    match list_type {
+        MediaType::ANIME => "anime-backup-",
+        MediaType::MANGA => "manga-backup-",
+    }
+}
+
+/// A new, unused path for a snapshot taken now, e.g. `anime-backup-2021-09-30T120000Z.toml`
+pub fn new_snapshot_path(dir: &Path, list_type: MediaType, extension: &str) -> PathBuf {
+    let timestamp = Utc::now().format(TIMESTAMP_FORMAT).to_string();
+    let mut path = dir.join(format!("{}{}.{}", prefix(list_type), timestamp, extension));
+    // two backups in the same second get a version number
+    let mut version = 1;
+    while path.exists() {
+        path = dir.join(format!(
+            "{}{}.{}.{}",
+            prefix(list_type),
+            timestamp,
+            version,
+            extension
+        ));
+        version += 1;
+    }
+    path
+}
+
+/// Every snapshot in the snapshot directory, newest first.
+/// `None` lists both anime and manga snapshots
+pub fn list_snapshots(dir: &Path, list_type: Option<MediaType>) -> Vec<Snapshot> {
+    let entries = match fs::read_dir(dir) {
+        Ok(entries) => entries,
+        // no backups have been made yet
+        Err(_) => return Vec::new(),
+    };
+
+    let mut snapshots: Vec<Snapshot> = entries
+        .filter_map(|entry| parse_snapshot(entry.ok()?.path()))
+        .filter(|snapshot| list_type.is_none() || list_type == Some(snapshot.list_type))
+        .collect();
+    snapshots.sort_by_key(|snapshot| Reverse((snapshot.taken_at, snapshot.version)));
+    snapshots
+}
+
+fn parse_snapshot(path: PathBuf) -> Option<Snapshot> {
+    let file_name = path.file_name()?.to_str()?;
+    let (list_type, rest) = if let Some(rest) = file_name.strip_prefix(prefix(MediaType::ANIME)) {
+        (MediaType::ANIME, rest)
+    } else if let Some(rest) = file_name.strip_prefix(prefix(MediaType::MANGA)) {
+        (MediaType::MANGA, rest)
+    } else {
+        return None;
+    };
+    // `<timestamp>.<extension>` or `<timestamp>.<version>.<extension>`
+    let parts: Vec<&str> = rest.split('.').collect();
+    let (timestamp, version, extension) = match parts[..] {
+        [timestamp, extension] => (timestamp, 0, extension),
+        [timestamp, version, extension] => (timestamp, version.parse().ok()?, extension),
+        _ => return None,
+    };
+    if !EXTENSIONS.contains(&extension) {
+        return None;
+    }
+    let taken_at = NaiveDateTime::parse_from_str(timestamp, TIMESTAMP_FORMAT).ok()?;
+
+    Some(Snapshot {
+        path: path.clone(),
+        list_type,
+        taken_at: Utc.from_utc_datetime(&taken_at),
+        version,
+    })
+}
+
+/// Deletes the snapshots of `list_type` that `retention` doesn't keep and returns them.
+/// Each format is pruned on its own, so a CSV or MAL XML export, which can't be restored,
+/// never takes the place of a TOML or JSON backup
+pub fn prune(dir: &Path, list_type: MediaType, retention: &Retention) -> Result<Vec<Snapshot>> {
+    let mut removed = Vec::new();
+    for extension in EXTENSIONS.iter() {
+        let snapshots: Vec<Snapshot> = list_snapshots(dir, Some(list_type))
+            .into_iter()
+            .filter(|snapshot| snapshot.path.extension() == Some(OsStr::new(extension)))
+            .collect();
+        let keep = snapshots_to_keep(&snapshots, retention);
+
+        for (i, snapshot) in snapshots.into_iter().enumerate() {
+            if !keep.contains(&i) {
+                fs::remove_file(&snapshot.path).map_err(Error::io(&snapshot.path))?;
+                removed.push(snapshot);
+            }
+        }
+    }
+    Ok(removed)
+}
+
+/// The indexes of the snapshots to keep. `snapshots` has to be sorted newest first.
+/// The newest is always kept, since it's usually the one that was just written
+fn snapshots_to_keep(snapshots: &[Snapshot], retention: &Retention) -> HashSet<usize> {
+    let mut keep = HashSet::new();
+    if !snapshots.is_empty() {
+        keep.insert(0);
+    }
+
+    if let Some(n) = retention.keep_last {
+        keep.extend(0..n.min(snapshots.len()));
+    }
+    if let Some(n) = retention.keep_daily {
+        keep_newest_per_period(snapshots, n, &mut keep, |date| {
+            (date.year(), date.ordinal())
+        });
+    }
+    if let Some(n) = retention.keep_weekly {
+        keep_newest_per_period(snapshots, n, &mut keep, |date| {
+            let week = date.iso_week();
+            (week.year(), week.week())
+        });
+    }
+    if let Some(n) = retention.keep_monthly {
+        keep_newest_per_period(snapshots, n, &mut keep, |date| (date.year(), date.month()));
+    }
+    keep
+}
+
+fn keep_newest_per_period<F>(snapshots: &[Snapshot], n: usize, keep: &mut HashSet<usize>, period: F)
+where
+    F: Fn(&DateTime<Utc>) -> (i32, u32),
+{
+    let mut periods = HashSet::new();
+    for (i, snapshot) in snapshots.iter().enumerate() {
+        if periods.len() == n {
+            break;
+        }
+        // the first one seen in a period is the newest
+        if periods.insert(period(&snapshot.taken_at)) {
+            keep.insert(i);
+        }
+    }
+}
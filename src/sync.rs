@@ -0,0 +1,426 @@
+use std::collections::HashSet;
+use std::fs;
+use std::io;
+
+use serde::{Deserialize, Serialize};
+
+use super::anilist_queries::{AnilistClient, FuzzyDate, MediaType};
+use super::error::{Error, Result};
+use super::mal_queries;
+use super::mappings::Mappings;
+use super::provider::{EntryValues, ListEntry, ListProvider, Service};
+use super::scores::ScoreConversion;
+
+/// Which way changes are pushed
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum Direction {
+    AnilistToMal,
+    MalToAnilist,
+    /// Whichever side was updated most recently wins
+    Both,
+}
+
+impl Direction {
+    pub fn from_arg(direction: &str) -> Result<Direction> {
+        match direction {
+            "anilist-to-mal" => Ok(Direction::AnilistToMal),
+            "mal-to-anilist" => Ok(Direction::MalToAnilist),
+            "both" => Ok(Direction::Both),
+            _ => Err(Error::Input(
+                "The value for 'direction' needs to be 'anilist-to-mal', 'mal-to-anilist' or 'both'"
+                    .to_string(),
+            )),
+        }
+    }
+}
+
+#[derive(Deserialize, Serialize, Debug, Copy, Clone, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum Action {
+    Create,
+    Update,
+}
+
+/// A single create or update on one of the services
+#[derive(Deserialize, Serialize, Debug)]
+pub struct Change {
+    pub action: Action,
+    pub target: Service,
+    pub title: String,
+    pub anilist_id: u32,
+    /// Only `None` for AniList changes to media that isn't on MAL
+    pub mal_id: Option<u32>,
+    /// What the target has now. `None` when creating
+    pub before: Option<EntryValues>,
+    pub after: EntryValues,
+}
+
+/// Every change a sync would make. Can be saved to a file and applied later
+#[derive(Deserialize, Serialize, Debug)]
+pub struct Plan {
+    pub list_type: MediaType,
+    pub changes: Vec<Change>,
+}
+
+/// Compares both lists and works out what needs to change on each side.
+/// `anilist_entries` should already have `mappings` applied. They're used here to find
+/// the AniList media for MAL entries that aren't on AniList yet
+pub async fn build_plan(
+    anilist: &AnilistClient<'_>,
+    mappings: &Mappings,
+    anilist_entries: &[ListEntry],
+    mal_entries: &[ListEntry],
+    list_type: MediaType,
+    direction: Direction,
+    scores: &ScoreConversion,
+) -> Result<Plan> {
+    let mut changes = Vec::new();
+    let mut matched_mal_ids = HashSet::new();
+
+    for anilist_entry in anilist_entries {
+        // only entries that are linked to MAL can be compared
+        let (anilist_id, id_mal) = match (anilist_entry.anilist_id, anilist_entry.mal_id) {
+            (Some(anilist_id), Some(id_mal)) => (anilist_id, id_mal),
+            _ => {
+                if direction != Direction::MalToAnilist {
+                    eprintln!("Couldn't find {} on MAL", anilist_entry.title);
+                }
+                continue;
+            }
+        };
+        matched_mal_ids.insert(id_mal);
+
+        let anilist_values = anilist_entry.values();
+        match mal_entries.iter().find(|x| x.mal_id == Some(id_mal)) {
+            Some(mal_entry) => {
+                let mal_values = mal_entry.values();
+                if !differs(&anilist_values, &mal_values, scores) {
+                    continue;
+                }
+                let target = match direction {
+                    Direction::AnilistToMal => Service::Mal,
+                    Direction::MalToAnilist => Service::Anilist,
+                    Direction::Both => older_side(anilist_entry, mal_entry),
+                };
+                let (before, after) = match target {
+                    Service::Mal => (mal_values, anilist_values),
+                    Service::Anilist => {
+                        let after = keep_anilist_detail(&anilist_values, mal_values, scores);
+                        (anilist_values, after)
+                    }
+                };
+                changes.push(Change {
+                    action: Action::Update,
+                    target,
+                    title: anilist_entry.title.clone(),
+                    anilist_id,
+                    mal_id: Some(id_mal),
+                    before: Some(before),
+                    after,
+                });
+            }
+            None => {
+                if direction == Direction::MalToAnilist {
+                    continue;
+                }
+                changes.push(Change {
+                    action: Action::Create,
+                    target: Service::Mal,
+                    title: anilist_entry.title.clone(),
+                    anilist_id,
+                    mal_id: Some(id_mal),
+                    before: None,
+                    after: anilist_values,
+                });
+            }
+        }
+    }
+
+    if direction != Direction::AnilistToMal {
+        let missing: Vec<(u32, &ListEntry)> = mal_entries
+            .iter()
+            .filter_map(|x| Some((x.mal_id?, x)))
+            .filter(|(id_mal, _)| !matched_mal_ids.contains(id_mal))
+            .collect();
+        let ids_mal: Vec<u32> = missing.iter().map(|(id_mal, _)| *id_mal).collect();
+        let anilist_ids = anilist.media_ids(&ids_mal, list_type).await?;
+
+        for (id_mal, mal_entry) in missing {
+            let anilist_id = mappings.anilist_id(id_mal).or_else(|| {
+                anilist_ids
+                    .get(&id_mal)
+                    .copied()
+                    .filter(|anilist_id| !mappings.is_blocked(*anilist_id, id_mal))
+            });
+            match anilist_id {
+                Some(anilist_id) => changes.push(Change {
+                    action: Action::Create,
+                    target: Service::Anilist,
+                    title: mal_entry.title.clone(),
+                    anilist_id,
+                    mal_id: Some(id_mal),
+                    before: None,
+                    // AniList only gets the converted score
+                    after: EntryValues {
+                        mal_score: None,
+                        ..mal_entry.values()
+                    },
+                }),
+                // stderr so it doesn't end up in the JSON plan
+                None => eprintln!("Couldn't find {} on AniList", mal_entry.title),
+            }
+        }
+    }
+
+    changes.iter().for_each(check_notes_length);
+    Ok(Plan { list_type, changes })
+}
+
+/// Applies every change in the plan. Unless `yes` is set, each one is confirmed first.
+/// Only the providers for services the plan has changes for are needed.
+/// A change the service rejects doesn't stop the rest, but anything else does
+pub async fn apply_plan(providers: &[&dyn ListProvider], plan: &Plan, yes: bool) -> Result<()> {
+    let mut failed = 0;
+    for change in plan.changes.iter() {
+        print_change(change);
+        if !yes {
+            let question = match change.target {
+                Service::Mal => "Update MAL?",
+                Service::Anilist => "Update AniList?",
+            };
+            if !confirm(question) {
+                continue;
+            }
+        }
+
+        let provider = providers
+            .iter()
+            .find(|provider| provider.service() == change.target)
+            .ok_or_else(|| Error::Config(format!("Not logged in to {}", change.target.name())))?;
+        let id = match change.target {
+            Service::Anilist => Some(change.anilist_id),
+            Service::Mal => change.mal_id,
+        };
+        let id = id.ok_or_else(|| {
+            Error::Input(format!(
+                "The change to {} has no {} id",
+                change.title,
+                change.target.name()
+            ))
+        })?;
+
+        match provider
+            .update_entry(plan.list_type, id, &change.after)
+            .await
+        {
+            Ok(()) => println!("\n Update complete \n"),
+            Err(Error::Api(service, message)) => {
+                eprintln!("\n {} rejected the update: {} \n", service, message);
+                failed += 1;
+            }
+            Err(error) => return Err(error),
+        }
+    }
+
+    if failed > 0 {
+        return Err(Error::UpdatesFailed(failed));
+    }
+    Ok(())
+}
+
+pub fn print_plan(plan: &Plan) {
+    if plan.changes.is_empty() {
+        println!("Nothing to change");
+        return;
+    }
+    for change in plan.changes.iter() {
+        print_change(change);
+        println!();
+    }
+    println!("{} changes planned", plan.changes.len());
+}
+
+pub fn write_plan(plan: &Plan, path: &str) -> Result<()> {
+    let json = serde_json::to_string_pretty(plan).unwrap();
+    fs::write(path, json).map_err(Error::io(path))
+}
+
+pub fn read_plan(path: &str) -> Result<Plan> {
+    let json = fs::read_to_string(path).map_err(Error::io(path))?;
+    serde_json::from_str(&json)
+        .map_err(|error| Error::Input(format!("Couldn't read the plan in {}: {}", path, error)))
+}
+
+fn print_change(change: &Change) {
+    let service = change.target.name();
+    println!("Title: {}", change.title);
+    match &change.before {
+        Some(before) => {
+            println!("Update on {}", service);
+            println!("Status: {:?} -> {:?}", before.status, change.after.status);
+            println!(
+                "Score: {} -> {}",
+                score_text(before),
+                score_text(&change.after)
+            );
+            println!("Progress: {} -> {}", before.progress, change.after.progress);
+            let volumes = (
+                before.progress_volumes.unwrap_or(0),
+                change.after.progress_volumes.unwrap_or(0),
+            );
+            if volumes.0 != volumes.1 {
+                println!("Volumes: {} -> {}", volumes.0, volumes.1);
+            }
+            if before.repeat != change.after.repeat {
+                println!("Repeat: {} -> {}", before.repeat, change.after.repeat);
+            }
+            for (name, before, after) in [
+                ("Started", before.started_at, change.after.started_at),
+                ("Finished", before.completed_at, change.after.completed_at),
+            ] {
+                if date_text(before) != date_text(after) {
+                    println!("{}: {} -> {}", name, date_text(before), date_text(after));
+                }
+            }
+            if before.notes != change.after.notes {
+                println!(
+                    "Notes: {} -> {}",
+                    notes_text(&before.notes),
+                    notes_text(&change.after.notes)
+                );
+            }
+        }
+        None => {
+            println!("You don't currently have an entry for this on {}", service);
+            println!("Status: {:?}", change.after.status);
+            println!("Score: {}", change.after.score);
+            println!("Progress: {}", change.after.progress);
+            if let Some(progress_volumes) =
+                change.after.progress_volumes.filter(|volumes| *volumes > 0)
+            {
+                println!("Volumes: {}", progress_volumes);
+            }
+            if change.after.repeat > 0 {
+                println!("Repeat: {}", change.after.repeat);
+            }
+            if !change.after.started_at.is_empty() {
+                println!("Started: {}", date_text(change.after.started_at));
+            }
+            if !change.after.completed_at.is_empty() {
+                println!("Finished: {}", date_text(change.after.completed_at));
+            }
+            if let Some(notes) = &change.after.notes {
+                println!("Notes: {}", notes);
+            }
+        }
+    }
+}
+
+/// Asks a yes/no question. Anything other than "y" is treated as no
+fn confirm(question: &str) -> bool {
+    println!("{} [y/n]", question);
+    let mut buffer = String::new();
+    // not being able to read an answer counts as no
+    let answer = io::stdin().read_line(&mut buffer).is_ok() && buffer.trim() == "y";
+    if !answer {
+        println!();
+    }
+    answer
+}
+
+/// Scores, dates and notes are compared the way MAL would store them, because it only has
+/// whole numbers from 0 to 10, can't have a month or day without the parts before it
+/// and cuts off long comments
+pub fn differs(anilist: &EntryValues, mal: &EntryValues, scores: &ScoreConversion) -> bool {
+    anilist.progress != mal.progress
+        // MAL doesn't have volumes for anime, and AniList has them as 0 or not at all
+        || anilist.progress_volumes.unwrap_or(0) != mal.progress_volumes.unwrap_or(0)
+        || anilist.status != mal.status
+        || anilist.repeat != mal.repeat
+        || scores.to_mal(anilist.score) != mal_score(mal, scores)
+        || mal_queries::format_date(anilist.started_at) != mal_queries::format_date(mal.started_at)
+        || mal_queries::format_date(anilist.completed_at)
+            != mal_queries::format_date(mal.completed_at)
+        || mal_queries::mal_comments(anilist.notes.as_deref())
+            != mal_queries::mal_comments(mal.notes.as_deref())
+}
+
+/// The score MAL has, falling back to converting `score` for values that didn't come from MAL
+fn mal_score(mal: &EntryValues, scores: &ScoreConversion) -> u8 {
+    mal.mal_score.unwrap_or_else(|| scores.to_mal(mal.score))
+}
+
+/// MAL's values to write to AniList. Where MAL only differs because it can't hold as much
+/// detail, e.g. a score of 85 that MAL has as 8, a day without a month or notes it cut off,
+/// AniList's value is kept instead
+fn keep_anilist_detail(
+    anilist: &EntryValues,
+    mut mal: EntryValues,
+    scores: &ScoreConversion,
+) -> EntryValues {
+    if scores.to_mal(anilist.score) == mal_score(&mal, scores) {
+        mal.score = anilist.score;
+    }
+    // AniList only gets the converted score
+    mal.mal_score = None;
+    if mal_queries::mal_comments(anilist.notes.as_deref())
+        == mal_queries::mal_comments(mal.notes.as_deref())
+    {
+        mal.notes = anilist.notes.clone();
+    }
+    if mal_queries::format_date(anilist.started_at) == mal_queries::format_date(mal.started_at) {
+        mal.started_at = anilist.started_at;
+    }
+    if mal_queries::format_date(anilist.completed_at) == mal_queries::format_date(mal.completed_at)
+    {
+        mal.completed_at = anilist.completed_at;
+    }
+    mal
+}
+
+/// Warns that only part of the notes will make it to MAL
+pub fn check_notes_length(change: &Change) {
+    let too_long = change.target == Service::Mal
+        && change
+            .after
+            .notes
+            .as_ref()
+            .map_or(0, |notes| notes.chars().count())
+            > mal_queries::MAX_COMMENTS_LENGTH;
+    if too_long {
+        eprintln!(
+            "The notes for {} are longer than MAL allows, so only the first {} characters will be synced",
+            change.title,
+            mal_queries::MAX_COMMENTS_LENGTH
+        );
+    }
+}
+
+fn notes_text(notes: &Option<String>) -> &str {
+    notes.as_deref().unwrap_or("not set")
+}
+
+/// The score with what MAL has for it, when it came from MAL
+fn score_text(values: &EntryValues) -> String {
+    match values.mal_score {
+        Some(mal_score) => format!("{} ({} on MAL)", values.score, mal_score),
+        None => values.score.to_string(),
+    }
+}
+
+fn date_text(date: FuzzyDate) -> String {
+    match mal_queries::format_date(date) {
+        text if text.is_empty() => "not set".to_string(),
+        text => text,
+    }
+}
+
+/// The side that should be updated. Ties go to AniList's values.
+/// Entries without a timestamp count as the oldest
+fn older_side(anilist_entry: &ListEntry, mal_entry: &ListEntry) -> Service {
+    if mal_entry.updated_at.unwrap_or(0) > anilist_entry.updated_at.unwrap_or(0) {
+        Service::Anilist
+    } else {
+        Service::Mal
+    }
+}
@@ -0,0 +1,198 @@
+mod common;
+
+use list_backup::anilist_queries::{self, FuzzyDate, MediaListStatus, MediaType};
+use list_backup::error::Error;
+use list_backup::provider::{EntryValues, ListEntry};
+use serde_json::json;
+use wiremock::matchers::{body_partial_json, body_string_contains, header, method, path};
+use wiremock::{Mock, MockServer, ResponseTemplate};
+
+#[tokio::test]
+async fn get_list_returns_each_entry_once() {
+    let server = MockServer::start().await;
+    let entry = common::anilist_entry(1, Some(10), "Show A", "CURRENT", 7.5, 3, 100);
+    let mut response = common::anilist_list(vec![entry.clone()]);
+    // the same entry again in a custom list
+    response["data"]["MediaListCollection"]["lists"]
+        .as_array_mut()
+        .unwrap()
+        .push(json!({
+            "name": "Favourites",
+            "isCustomList": true,
+            "isSplitCompletedList": false,
+            "status": null,
+            "entries": [entry]
+        }));
+    Mock::given(method("POST"))
+        .and(path("/graphql"))
+        .and(header("Authorization", "Bearer access-token"))
+        .and(body_string_contains("MediaListCollection"))
+        .respond_with(ResponseTemplate::new(200).set_body_json(response))
+        .expect(1)
+        .mount(&server)
+        .await;
+
+    let lists = anilist_queries::get_list(
+        &common::anilist_config(),
+        &common::urls(&server),
+        MediaType::ANIME,
+    )
+    .await
+    .unwrap();
+
+    assert_eq!(lists.lists.len(), 2);
+    assert!(lists.lists[1].is_custom_list);
+    let entries = lists.list_entries();
+    assert_eq!(entries.len(), 1);
+    assert_eq!(entries[0].title, "Show A");
+    assert_eq!(entries[0].anilist_id, Some(1));
+    assert_eq!(entries[0].mal_id, Some(10));
+    assert_eq!(entries[0].status, MediaListStatus::Current);
+    assert_eq!(entries[0].progress, 3);
+}
+
+#[tokio::test]
+async fn update_entry_sends_the_values() {
+    let server = MockServer::start().await;
+    Mock::given(method("POST"))
+        .and(path("/graphql"))
+        .and(body_string_contains("SaveMediaListEntry"))
+        .and(body_partial_json(json!({
+            "variables": {"media_id": 1, "status": "COMPLETED", "score": 8.5, "progress": 12, "repeat": 1,
+                "started_at": {"year": 2021, "month": 10, "day": null},
+                "completed_at": {"year": null, "month": null, "day": null},
+                "notes": "",
+                // left as they are on AniList
+                "private": null, "custom_lists": null, "advanced_scores": null}
+        })))
+        .respond_with(
+            ResponseTemplate::new(200)
+                .set_body_json(json!({"data": {"SaveMediaListEntry": {"id": 5}}})),
+        )
+        .expect(1)
+        .mount(&server)
+        .await;
+
+    anilist_queries::update_entry(
+        &common::anilist_config(),
+        &common::urls(&server),
+        MediaType::ANIME,
+        1,
+        &EntryValues {
+            status: MediaListStatus::Completed,
+            score: 8.5,
+            mal_score: None,
+            progress: 12,
+            progress_volumes: None,
+            repeat: 1,
+            started_at: FuzzyDate {
+                year: Some(2021),
+                month: Some(10),
+                day: None,
+            },
+            completed_at: FuzzyDate::default(),
+            notes: None,
+            private: None,
+            hidden_from_status_lists: None,
+            custom_lists: None,
+            advanced_scores: None,
+        },
+    )
+    .await
+    .unwrap();
+}
+
+#[tokio::test]
+async fn update_entry_sends_the_fields_only_anilist_has() {
+    let server = MockServer::start().await;
+    Mock::given(method("POST"))
+        .and(path("/graphql"))
+        .and(body_string_contains("advancedScoring"))
+        .respond_with(ResponseTemplate::new(200).set_body_json(json!({
+            "data": {"Viewer": {"mediaListOptions": {
+                "scoreFormat": "POINT_10",
+                "animeList": {"advancedScoring": ["Story", "Art"]},
+                "mangaList": {"advancedScoring": []}
+            }}}
+        })))
+        .expect(1)
+        .mount(&server)
+        .await;
+    // advanced scores go in the order of the user's categories, and ones they've removed are dropped
+    Mock::given(method("POST"))
+        .and(path("/graphql"))
+        .and(body_string_contains("SaveMediaListEntry"))
+        .and(body_partial_json(json!({
+            "variables": {"private": true, "hidden_from_status_lists": false,
+                "custom_lists": ["Favourites"], "advanced_scores": [9.0, 0.0]}
+        })))
+        .respond_with(
+            ResponseTemplate::new(200)
+                .set_body_json(json!({"data": {"SaveMediaListEntry": {"id": 5}}})),
+        )
+        .expect(1)
+        .mount(&server)
+        .await;
+
+    let mut entry = ListEntry::new("Show A".to_string(), MediaListStatus::Completed, 8.0, 12);
+    entry.private = true;
+    entry.custom_lists = vec!["Favourites".to_string()];
+    entry.advanced_scores.insert("Story".to_string(), 9.0);
+    entry.advanced_scores.insert("Music".to_string(), 3.0);
+    anilist_queries::update_entry(
+        &common::anilist_config(),
+        &common::urls(&server),
+        MediaType::ANIME,
+        1,
+        &entry.anilist_values(),
+    )
+    .await
+    .unwrap();
+}
+
+#[tokio::test]
+async fn graphql_errors_are_returned() {
+    let server = MockServer::start().await;
+    Mock::given(method("POST"))
+        .and(path("/graphql"))
+        .respond_with(ResponseTemplate::new(400).set_body_json(json!({
+            "errors": [{"message": "Validation error", "status": 400}],
+            "data": null
+        })))
+        .mount(&server)
+        .await;
+
+    let result = anilist_queries::get_list(
+        &common::anilist_config(),
+        &common::urls(&server),
+        MediaType::ANIME,
+    )
+    .await;
+
+    match result {
+        Err(Error::Api(_, message)) => assert_eq!(message, "Validation error"),
+        other => panic!("Expected an API error, got {:?}", other),
+    }
+}
+
+#[tokio::test]
+async fn a_rejected_token_is_auth_expired() {
+    let server = MockServer::start().await;
+    Mock::given(method("POST"))
+        .and(path("/graphql"))
+        .respond_with(ResponseTemplate::new(400).set_body_json(json!({
+            "errors": [{"message": "Invalid token", "status": 400}],
+            "data": null
+        })))
+        .mount(&server)
+        .await;
+
+    let result = anilist_queries::get_list(
+        &common::anilist_config(),
+        &common::urls(&server),
+        MediaType::ANIME,
+    )
+    .await;
+
+    assert!(matches!(result, Err(Error::AuthExpired(_))));
+}
@@ -0,0 +1,298 @@
+mod common;
+
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Output};
+
+use list_backup::anilist_queries::{MediaListStatus, MediaType, ScoreFormat};
+use list_backup::config::{BackupConfig, TomlConfig};
+use list_backup::error::Error;
+use list_backup::provider::ListEntry;
+use list_backup::save_to_file::{self, Format};
+use list_backup::scores::{Rounding, ScoreConversion};
+use list_backup::snapshots;
+use wiremock::matchers::{body_string_contains, method, path};
+use wiremock::{Mock, MockServer, ResponseTemplate};
+
+/// Writes the entries in `format` and returns what was written
+fn write(name: &str, entries: &[ListEntry], format: &Format) -> String {
+    let path = common::temp_home(name).join(format!("backup.{}", format.extension()));
+    save_to_file::write_list_to_file(
+        entries,
+        vec![],
+        &common::user(),
+        MediaType::ANIME,
+        ScoreFormat::Point100,
+        format,
+        &save_to_file::Output::File(path.clone()),
+    )
+    .unwrap();
+    fs::read_to_string(path).unwrap()
+}
+
+#[test]
+fn toml_backups_without_lists_can_be_written() {
+    let dir = common::temp_home("backup-toml");
+    let path = dir.join("backup.toml");
+
+    let entries = [common::list_entry(
+        1,
+        Some(10),
+        "Show A",
+        MediaListStatus::Completed,
+    )];
+    save_to_file::write_list_to_file(
+        &entries,
+        vec![],
+        &common::user(),
+        MediaType::ANIME,
+        ScoreFormat::Point100,
+        &Format::Toml,
+        &save_to_file::Output::File(path.clone()),
+    )
+    .unwrap();
+    let backup = save_to_file::read_backup(&path).unwrap();
+    assert_eq!(backup.entries().count(), 1);
+    assert!(backup.lists.is_empty());
+    assert_eq!(
+        backup.user_section.score_format,
+        Some(ScoreFormat::Point100)
+    );
+
+    // someone with nothing on their list
+    save_to_file::write_list_to_file(
+        &[],
+        vec![],
+        &common::user(),
+        MediaType::MANGA,
+        ScoreFormat::Point10,
+        &Format::Toml,
+        &save_to_file::Output::File(path.clone()),
+    )
+    .unwrap();
+    let backup = save_to_file::read_backup(&path).unwrap();
+    assert_eq!(backup.entries().count(), 0);
+}
+
+#[test]
+fn csv_fields_are_quoted() {
+    let entries = [
+        common::list_entry(1, Some(10), "Show, \"A\"", MediaListStatus::Completed),
+        common::list_entry(2, None, "Line\nBreak", MediaListStatus::Completed),
+        common::list_entry(3, Some(30), "Plain", MediaListStatus::Completed),
+    ];
+    let format = Format::from_arg(
+        "csv",
+        false,
+        Some("title, id_mal,score"),
+        ScoreConversion::default(),
+    )
+    .unwrap();
+
+    assert_eq!(
+        write("backup-csv", &entries, &format),
+        "title,id_mal,score\r\n\"Show, \"\"A\"\"\",10,8\r\n\"Line\nBreak\",,8\r\nPlain,30,8\r\n"
+    );
+
+    let unknown = Format::from_arg(
+        "csv",
+        false,
+        Some("title,rating"),
+        ScoreConversion::default(),
+    );
+    assert!(matches!(unknown, Err(Error::Input(_))));
+}
+
+#[test]
+fn mal_xml_escapes_text_and_converts_scores() {
+    let mut show = common::list_entry(1, Some(10), "Show ]]> A", MediaListStatus::Repeating);
+    show.score = 85.0;
+    show.notes = Some("<b>Rewatch</b> ]]>".to_string());
+    let entries = [
+        show,
+        common::list_entry(2, None, "Not on MAL", MediaListStatus::Completed),
+    ];
+    let format = Format::MalXml {
+        scores: ScoreConversion {
+            format: ScoreFormat::Point100,
+            rounding: Rounding::Nearest,
+        },
+    };
+
+    let xml = write("backup-xml", &entries, &format);
+    // the entry without a MAL id is left out
+    assert_eq!(xml.matches("<anime>").count(), 1);
+    assert!(xml.contains("<series_animedb_id>10</series_animedb_id>"));
+    // a `]]>` can't end the CDATA section early
+    assert!(xml.contains("<series_title><![CDATA[Show ]]]]><![CDATA[> A]]></series_title>"));
+    assert!(xml.contains("<my_comments><![CDATA[<b>Rewatch</b> ]]]]><![CDATA[>]]></my_comments>"));
+    assert!(xml.contains("<my_score>9</my_score>"));
+    // rewatching is completed with the flag set
+    assert!(xml.contains("<my_status>Completed</my_status>"));
+    assert!(xml.contains("<my_rewatching>1</my_rewatching>"));
+    assert!(xml.contains("<my_start_date>0000-00-00</my_start_date>"));
+}
+
+#[test]
+fn json_backups_leave_out_fields_that_arent_set() {
+    let mut show = common::list_entry(1, Some(10), "Show A", MediaListStatus::Completed);
+    show.notes = Some("Watch the dub".to_string());
+    let entries = [
+        show,
+        common::list_entry(2, Some(20), "Show B", MediaListStatus::Completed),
+    ];
+    let json = write("backup-json", &entries, &Format::Json { compact: true });
+    let backup: serde_json::Value = serde_json::from_str(&json).unwrap();
+
+    let completed = backup["completed"].as_array().unwrap();
+    assert_eq!(completed[0]["notes"], "Watch the dub");
+    let show_b = completed[1].as_object().unwrap();
+    for field in [
+        "progress_volumes",
+        "notes",
+        "created_at",
+        "updated_at",
+        "started_at",
+        "completed_at",
+    ] {
+        assert!(!show_b.contains_key(field), "{}", field);
+    }
+    // and they're read back in as not set
+    let path = common::temp_home("backup-json-read").join("backup.json");
+    fs::write(&path, json).unwrap();
+    let backup = save_to_file::read_backup(&path).unwrap();
+    assert_eq!(backup.entries().nth(1).unwrap().notes, None);
+}
+
+#[test]
+fn json_backups_have_a_schema_version_and_read_back_in() {
+    let entries = [
+        common::list_entry(1, Some(10), "Show A", MediaListStatus::Completed),
+        common::list_entry(2, None, "Show B", MediaListStatus::Planning),
+    ];
+    let pretty = write(
+        "backup-json-pretty",
+        &entries,
+        &Format::Json { compact: false },
+    );
+    let compact = write(
+        "backup-json-compact",
+        &entries,
+        &Format::Json { compact: true },
+    );
+    assert!(pretty.lines().count() > 1);
+    assert_eq!(compact.trim_end().lines().count(), 1);
+
+    let json: serde_json::Value = serde_json::from_str(&pretty).unwrap();
+    assert_eq!(json["schema_version"], save_to_file::SCHEMA_VERSION);
+    assert_eq!(
+        json,
+        serde_json::from_str::<serde_json::Value>(&compact).unwrap()
+    );
+
+    let path = common::temp_home("backup-json-round-trip").join("backup.json");
+    fs::write(&path, &pretty).unwrap();
+    let backup = save_to_file::read_backup(&path).unwrap();
+    assert_eq!(backup.schema_version, save_to_file::SCHEMA_VERSION);
+    assert_eq!(backup.user_section.username, common::user().name);
+    let titles: Vec<&str> = backup.entries().map(|entry| entry.title.as_str()).collect();
+    assert_eq!(titles, ["Show A", "Show B"]);
+
+    // a backup from a newer version is refused rather than half read
+    let mut json = json;
+    json["schema_version"] = (save_to_file::SCHEMA_VERSION + 1).into();
+    fs::write(&path, json.to_string()).unwrap();
+    assert!(matches!(
+        save_to_file::read_backup(&path),
+        Err(Error::Input(_))
+    ));
+}
+
+/// Runs `backup anime` with a config logged in to AniList that saves snapshots in `directory`
+fn run_backup(
+    server: &MockServer,
+    home: &Path,
+    directory: Option<PathBuf>,
+    args: &[&str],
+) -> Output {
+    let config = TomlConfig {
+        anilist: Some(common::anilist_config()),
+        backup: Some(BackupConfig { directory }),
+        api: Some(common::urls(server)),
+        ..TomlConfig::default()
+    };
+    common::write_config(home, &config);
+
+    Command::new(env!("CARGO_BIN_EXE_list-backup"))
+        .args(["backup", "anime"])
+        .args(args)
+        .env("HOME", home)
+        .env("XDG_DATA_HOME", home.join("data"))
+        .env("ANILIST_SECRET", "anilist-secret")
+        .current_dir(home)
+        .output()
+        .unwrap()
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn backups_go_where_they_are_asked_to() {
+    let server = MockServer::start().await;
+    common::mount_score_format(&server, "POINT_10").await;
+    Mock::given(method("POST"))
+        .and(path("/graphql"))
+        .and(body_string_contains("MediaListCollection"))
+        .respond_with(
+            ResponseTemplate::new(200).set_body_json(common::anilist_list(vec![
+                common::anilist_entry(1, Some(10), "Show A", "CURRENT", 7.0, 3, 1633132800),
+            ])),
+        )
+        .mount(&server)
+        .await;
+    let home = common::temp_home("backup-output");
+
+    // the configured directory is made if it doesn't exist yet
+    let snapshot_dir = home.join("backups");
+    let output = run_backup(&server, &home, Some(snapshot_dir.clone()), &[]);
+    assert!(
+        output.status.success(),
+        "{}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+    assert_eq!(
+        snapshots::list_snapshots(&snapshot_dir, Some(MediaType::ANIME)).len(),
+        1
+    );
+
+    // and so are the parents of --output
+    let output = run_backup(
+        &server,
+        &home,
+        Some(snapshot_dir.clone()),
+        &["--output", "exports/anime.json", "--format", "json"],
+    );
+    assert!(output.status.success());
+    assert!(fs::read_to_string(home.join("exports").join("anime.json"))
+        .unwrap()
+        .contains("\"schema_version\""));
+
+    let output = run_backup(
+        &server,
+        &home,
+        Some(snapshot_dir.clone()),
+        &["--output", "-"],
+    );
+    assert!(output.status.success());
+    assert!(String::from_utf8_lossy(&output.stdout).contains("Show A"));
+    assert_eq!(
+        snapshots::list_snapshots(&snapshot_dir, Some(MediaType::ANIME)).len(),
+        1
+    );
+
+    // without a configured directory or ~/Documents, snapshots go in the XDG data directory
+    let output = run_backup(&server, &home, None, &[]);
+    assert!(output.status.success());
+    assert_eq!(
+        snapshots::list_snapshots(&home.join("data").join("list-backup"), None).len(),
+        1
+    );
+}
@@ -0,0 +1,220 @@
+// each test file only uses some of these
+#![allow(dead_code)]
+
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use list_backup::anilist_queries::{MediaFormat, MediaListStatus, MediaType, ScoreFormat};
+use list_backup::config::{AnilistConfig, ApiUrls, MALConfig, TomlConfig};
+use list_backup::provider::{ListEntry, User};
+use list_backup::save_to_file::{self, BackupToml, Format, Output};
+use serde_json::{json, Value};
+use wiremock::matchers::{body_string_contains, method, path};
+use wiremock::{Mock, MockServer, ResponseTemplate};
+
+pub const ACCESS_TOKEN: &str = "access-token";
+
+/// Every API on the one fake server, told apart by path
+pub fn urls(server: &MockServer) -> ApiUrls {
+    ApiUrls {
+        anilist_graphql: format!("{}/graphql", server.uri()),
+        anilist_oauth: format!("{}/anilist/oauth", server.uri()),
+        mal_api: format!("{}/mal/v2", server.uri()),
+        mal_oauth: format!("{}/mal/oauth2", server.uri()),
+    }
+}
+
+pub fn anilist_config() -> AnilistConfig {
+    AnilistConfig {
+        token_type: "Bearer".to_string(),
+        expires_in: 31536000,
+        // far enough away that it's never refreshed
+        expires_at: u64::MAX / 2,
+        access_token: ACCESS_TOKEN.to_string(),
+        refresh_token: "refresh-token".to_string(),
+        code: "code".to_string(),
+        user_id: 1,
+        user_name: "user".to_string(),
+        score_format: Some(ScoreFormat::Point10),
+    }
+}
+
+pub fn mal_config() -> MALConfig {
+    MALConfig {
+        token_type: "Bearer".to_string(),
+        expires_in: 2678400,
+        expires_at: u64::MAX / 2,
+        access_token: ACCESS_TOKEN.to_string(),
+        refresh_token: "refresh-token".to_string(),
+        code: "code".to_string(),
+        pkce: "pkce".to_string(),
+    }
+}
+
+/// An empty directory to use as `HOME`, so the config file doesn't touch the real one
+pub fn temp_home(name: &str) -> PathBuf {
+    let dir = std::env::temp_dir().join(format!("list-backup-{}-{}", name, std::process::id()));
+    let _ = fs::remove_dir_all(&dir);
+    fs::create_dir_all(&dir).unwrap();
+    dir
+}
+
+/// Writes the config file the CLI reads when run with `home` as `HOME`
+pub fn write_config(home: &Path, config: &TomlConfig) {
+    let config_dir = home.join(".config").join("list-backup");
+    fs::create_dir_all(&config_dir).unwrap();
+    fs::write(
+        config_dir.join("config.toml"),
+        toml::to_string(config).unwrap(),
+    )
+    .unwrap();
+}
+
+pub fn anilist_entry(
+    media_id: u32,
+    id_mal: Option<u32>,
+    title: &str,
+    status: &str,
+    score: f32,
+    progress: u32,
+    updated_at: i64,
+) -> Value {
+    json!({
+        "status": status,
+        "score": score,
+        "progress": progress,
+        "progressVolumes": null,
+        "repeat": 0,
+        "notes": null,
+        "private": false,
+        "hiddenFromStatusLists": false,
+        "customLists": null,
+        "advancedScores": null,
+        "startedAt": {"year": null, "month": null, "day": null},
+        "completedAt": {"year": null, "month": null, "day": null},
+        "createdAt": updated_at,
+        "updatedAt": updated_at,
+        "media": {
+            "id": media_id,
+            "idMal": id_mal,
+            "title": {"userPreferred": title},
+            "format": "TV",
+            "episodes": 12,
+            "chapters": null,
+            "startDate": {"year": 2021}
+        }
+    })
+}
+
+/// A `MediaListCollection` response with a single list holding `entries`
+pub fn anilist_list(entries: Vec<Value>) -> Value {
+    json!({
+        "data": {
+            "MediaListCollection": {
+                "lists": [{
+                    "name": "Watching",
+                    "isCustomList": false,
+                    "isSplitCompletedList": false,
+                    "status": "CURRENT",
+                    "entries": entries
+                }]
+            }
+        }
+    })
+}
+
+pub fn mal_entry(
+    id: u32,
+    title: &str,
+    status: &str,
+    score: u8,
+    episodes: u32,
+    updated_at: &str,
+) -> Value {
+    json!({
+        "node": {
+            "id": id,
+            "title": title,
+            "main_picture": {"medium": "medium.jpg", "large": "large.jpg"}
+        },
+        "list_status": {
+            "status": status,
+            "score": score,
+            "num_episodes_watched": episodes,
+            "is_rewatching": false,
+            "updated_at": updated_at
+        }
+    })
+}
+
+pub fn mal_page(entries: Vec<Value>, next: Option<String>) -> Value {
+    json!({ "data": entries, "paging": {"next": next} })
+}
+
+pub fn token_response(access_token: &str) -> Value {
+    json!({
+        "token_type": "Bearer",
+        "expires_in": 31536000,
+        "access_token": access_token,
+        "refresh_token": "new-refresh-token"
+    })
+}
+
+/// Answers the query for the user's score format, which every sync starts with
+pub async fn mount_score_format(server: &MockServer, score_format: &str) {
+    Mock::given(method("POST"))
+        .and(path("/graphql"))
+        .and(body_string_contains("mediaListOptions"))
+        .respond_with(ResponseTemplate::new(200).set_body_json(json!({
+            "data": {"Viewer": {"mediaListOptions": {"scoreFormat": score_format}}}
+        })))
+        .mount(server)
+        .await;
+}
+
+/// Answers the request for the MAL user, which checks the MAL token before every sync
+pub async fn mount_mal_user(server: &MockServer) {
+    Mock::given(method("GET"))
+        .and(path("/mal/v2/users/@me"))
+        .respond_with(ResponseTemplate::new(200).set_body_json(json!({"id": 1, "name": "user"})))
+        .mount(server)
+        .await;
+}
+
+pub fn user() -> User {
+    User {
+        id: 1,
+        name: "user".to_string(),
+    }
+}
+
+/// An AniList entry with everything a backup needs
+pub fn list_entry(
+    anilist_id: u32,
+    mal_id: Option<u32>,
+    title: &str,
+    status: MediaListStatus,
+) -> ListEntry {
+    let mut entry = ListEntry::new(title.to_string(), status, 8.0, 12);
+    entry.anilist_id = Some(anilist_id);
+    entry.mal_id = mal_id;
+    entry.format = Some(MediaFormat::Tv);
+    entry.episodes = Some(12);
+    entry
+}
+
+/// Writes the entries to a JSON backup in `dir` and reads it back in
+pub fn backup(dir: &Path, name: &str, entries: &[ListEntry]) -> BackupToml {
+    let path = dir.join(format!("{}.json", name));
+    save_to_file::write_list_to_file(
+        entries,
+        vec![],
+        &user(),
+        MediaType::ANIME,
+        ScoreFormat::Point10,
+        &Format::Json { compact: true },
+        &Output::File(path.clone()),
+    )
+    .unwrap();
+    save_to_file::read_backup(&path).unwrap()
+}
@@ -0,0 +1,78 @@
+mod common;
+
+use std::env;
+#[cfg(unix)]
+use std::os::unix::fs::PermissionsExt;
+use std::path::PathBuf;
+
+use list_backup::config::{self, BackupConfig, TomlConfig};
+use serde_json::json;
+use wiremock::matchers::{body_string_contains, method, path};
+use wiremock::{Mock, MockServer, ResponseTemplate};
+
+// one test, because it changes environment variables for the whole process
+#[tokio::test]
+async fn logging_in_keeps_the_rest_of_the_config() {
+    let server = MockServer::start().await;
+    let urls = common::urls(&server);
+    let home = common::temp_home("config");
+    env::set_var("HOME", &home);
+    env::set_var("ANILIST_SECRET", "anilist-secret");
+    env::set_var("MAL_CLIENT_ID", "mal-client-id");
+    env::set_var("MAL_SECRET", "mal-secret");
+
+    Mock::given(method("POST"))
+        .and(path("/anilist/oauth/token"))
+        .respond_with(
+            ResponseTemplate::new(200).set_body_json(common::token_response("anilist-token")),
+        )
+        .mount(&server)
+        .await;
+    Mock::given(method("POST"))
+        .and(path("/graphql"))
+        .and(body_string_contains("UpdateUser"))
+        .respond_with(ResponseTemplate::new(200).set_body_json(json!({
+            "data": {"UpdateUser": {"id": 1, "name": "user"}}
+        })))
+        .mount(&server)
+        .await;
+    common::mount_score_format(&server, "POINT_10").await;
+    Mock::given(method("POST"))
+        .and(path("/mal/oauth2/token"))
+        .respond_with(ResponseTemplate::new(200).set_body_json(common::token_response("mal-token")))
+        .mount(&server)
+        .await;
+
+    // something the user set up before logging in
+    config::save_config(&TomlConfig {
+        backup: Some(BackupConfig {
+            directory: Some(PathBuf::from("/srv/backups")),
+        }),
+        ..TomlConfig::default()
+    })
+    .unwrap();
+
+    config::get_anilist_token(&urls, "anilist-code")
+        .await
+        .unwrap();
+    config::get_mal_token(&urls, "mal-code", "pkce")
+        .await
+        .unwrap();
+
+    let saved = config::load_config().unwrap();
+    assert_eq!(saved.anilist.unwrap().access_token, "anilist-token");
+    assert_eq!(saved.myanimelist.unwrap().access_token, "mal-token");
+    assert_eq!(
+        saved.backup.unwrap().directory,
+        Some(PathBuf::from("/srv/backups"))
+    );
+
+    let config_path = config::config_path().unwrap();
+    // the tokens are only readable by the user
+    #[cfg(unix)]
+    assert_eq!(
+        config_path.metadata().unwrap().permissions().mode() & 0o777,
+        0o600
+    );
+    assert!(!config_path.with_extension("toml.tmp").exists());
+}
@@ -0,0 +1,59 @@
+mod common;
+
+use list_backup::anilist_queries::MediaListStatus;
+use list_backup::diff;
+
+#[test]
+fn backups_are_compared_by_anilist_id() {
+    let dir = common::temp_home("diff");
+    let old = common::backup(
+        &dir,
+        "old",
+        &[
+            common::list_entry(1, Some(10), "Show A", MediaListStatus::Current),
+            common::list_entry(2, Some(20), "Show B", MediaListStatus::Completed),
+            common::list_entry(3, None, "Show C", MediaListStatus::Planning),
+        ],
+    );
+
+    let mut show_a = common::list_entry(1, Some(10), "Show A", MediaListStatus::Completed);
+    show_a.score = 9.0;
+    show_a.progress = 24;
+    let mut show_d = common::list_entry(4, Some(40), "Show D", MediaListStatus::Current);
+    show_d.progress = 1;
+    let new = common::backup(
+        &dir,
+        "new",
+        &[
+            show_a,
+            common::list_entry(2, Some(20), "Show B", MediaListStatus::Completed),
+            show_d,
+        ],
+    );
+
+    let backup_diff = diff::diff_backups(&old, &new);
+    assert_eq!(backup_diff.added.len(), 1);
+    assert_eq!(backup_diff.added[0].id, 4);
+    assert_eq!(backup_diff.removed.len(), 1);
+    assert_eq!(backup_diff.removed[0].title, "Show C");
+
+    // Show B is the same in both, so it isn't listed
+    assert_eq!(backup_diff.changed.len(), 1);
+    let change = &backup_diff.changed[0];
+    assert_eq!(change.id, 1);
+    let status = change.status.as_ref().unwrap();
+    assert_eq!(status.from, MediaListStatus::Current);
+    assert_eq!(status.to, MediaListStatus::Completed);
+    let score = change.score.as_ref().unwrap();
+    assert_eq!((score.from, score.to), (8.0, 9.0));
+    let progress = change.progress.as_ref().unwrap();
+    assert_eq!((progress.from, progress.to, progress.delta), (12, 24, 12));
+
+    // and the other way round
+    let backup_diff = diff::diff_backups(&new, &old);
+    assert_eq!(backup_diff.added[0].id, 3);
+    assert_eq!(backup_diff.removed[0].id, 4);
+    assert_eq!(backup_diff.changed[0].progress.as_ref().unwrap().delta, -12);
+
+    assert!(diff::diff_backups(&old, &old).is_empty());
+}
@@ -0,0 +1,98 @@
+mod common;
+
+use std::fs;
+use std::net::TcpListener;
+use std::path::Path;
+use std::process::{Command, Output};
+
+use list_backup::config::{ApiUrls, TomlConfig};
+use serde_json::json;
+use wiremock::matchers::{method, path};
+use wiremock::{Mock, MockServer, ResponseTemplate};
+
+fn run(home: &Path, args: &[&str]) -> Output {
+    Command::new(env!("CARGO_BIN_EXE_list-backup"))
+        .args(args)
+        .env("HOME", home)
+        .env("ANILIST_SECRET", "anilist-secret")
+        .current_dir(home)
+        .output()
+        .unwrap()
+}
+
+/// A config logged in to AniList that sends its queries to `anilist_graphql`
+fn anilist_config(anilist_graphql: String) -> TomlConfig {
+    TomlConfig {
+        anilist: Some(common::anilist_config()),
+        api: Some(ApiUrls {
+            anilist_graphql,
+            ..ApiUrls::default()
+        }),
+        ..TomlConfig::default()
+    }
+}
+
+#[test]
+fn bad_arguments_exit_with_2() {
+    let home = common::temp_home("errors-input");
+    let output = run(&home, &["backup", "films"]);
+    assert_eq!(output.status.code(), Some(2));
+    assert!(String::from_utf8_lossy(&output.stderr).contains("'anime' or 'manga'"));
+}
+
+#[test]
+fn bad_config_files_exit_with_3() {
+    let home = common::temp_home("errors-config");
+    let config_dir = home.join(".config").join("list-backup");
+    fs::create_dir_all(&config_dir).unwrap();
+    fs::write(config_dir.join("config.toml"), "[anilist\n").unwrap();
+
+    let output = run(&home, &["backup", "anime"]);
+    assert_eq!(output.status.code(), Some(3));
+    assert!(String::from_utf8_lossy(&output.stderr).contains("config file"));
+}
+
+#[test]
+fn missing_files_exit_with_4() {
+    let home = common::temp_home("errors-io");
+    let output = run(&home, &["diff", "old.toml", "new.toml"]);
+    assert_eq!(output.status.code(), Some(4));
+    assert!(String::from_utf8_lossy(&output.stderr).contains("old.toml"));
+}
+
+#[test]
+fn unreachable_services_exit_with_5() {
+    // nothing is listening on the port once the listener is dropped
+    let port = TcpListener::bind("127.0.0.1:0")
+        .unwrap()
+        .local_addr()
+        .unwrap()
+        .port();
+    let home = common::temp_home("errors-network");
+    common::write_config(
+        &home,
+        &anilist_config(format!("http://127.0.0.1:{}/graphql", port)),
+    );
+
+    let output = run(&home, &["backup", "anime"]);
+    assert_eq!(output.status.code(), Some(5));
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn api_errors_exit_with_7() {
+    let server = MockServer::start().await;
+    Mock::given(method("POST"))
+        .and(path("/graphql"))
+        .respond_with(ResponseTemplate::new(429).set_body_json(json!({
+            "data": null,
+            "errors": [{"message": "Too Many Requests.", "status": 429}]
+        })))
+        .mount(&server)
+        .await;
+    let home = common::temp_home("errors-api");
+    common::write_config(&home, &anilist_config(format!("{}/graphql", server.uri())));
+
+    let output = run(&home, &["backup", "anime"]);
+    assert_eq!(output.status.code(), Some(7));
+    assert!(String::from_utf8_lossy(&output.stderr).contains("Too Many Requests."));
+}
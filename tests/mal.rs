@@ -0,0 +1,383 @@
+mod common;
+
+use list_backup::anilist_queries::MediaType;
+use list_backup::anilist_queries::{FuzzyDate, MediaListStatus};
+use list_backup::error::Error;
+use list_backup::mal_queries::{self, ListUpdate, Status};
+use list_backup::scores::ScoreConversion;
+use serde_json::json;
+use wiremock::matchers::{body_string, header, method, path, query_param, query_param_is_missing};
+use wiremock::{Mock, MockServer, ResponseTemplate};
+
+#[tokio::test]
+async fn get_list_follows_every_page() {
+    let server = MockServer::start().await;
+    let next = format!(
+        "{}/mal/v2/users/@me/animelist?offset=1&fields=list_status&limit=1",
+        server.uri()
+    );
+    Mock::given(method("GET"))
+        .and(path("/mal/v2/users/@me/animelist"))
+        .and(query_param("limit", "1"))
+        .and(query_param_is_missing("offset"))
+        .and(header("Authorization", "Bearer access-token"))
+        .respond_with(ResponseTemplate::new(200).set_body_json(common::mal_page(
+            vec![common::mal_entry(
+                10,
+                "Show A",
+                "watching",
+                7,
+                3,
+                "2021-10-01T00:00:00+00:00",
+            )],
+            Some(next),
+        )))
+        .expect(1)
+        .mount(&server)
+        .await;
+    Mock::given(method("GET"))
+        .and(path("/mal/v2/users/@me/animelist"))
+        .and(query_param("offset", "1"))
+        .respond_with(ResponseTemplate::new(200).set_body_json(common::mal_page(
+            vec![common::mal_entry(
+                20,
+                "Show B",
+                "completed",
+                9,
+                12,
+                "2021-10-02T00:00:00+00:00",
+            )],
+            None,
+        )))
+        .expect(1)
+        .mount(&server)
+        .await;
+
+    let list = mal_queries::get_list(
+        &common::mal_config(),
+        &common::urls(&server),
+        MediaType::ANIME,
+        1,
+    )
+    .await
+    .unwrap();
+
+    let ids: Vec<u32> = list.data.iter().map(|entry| entry.node.id).collect();
+    assert_eq!(ids, vec![10, 20]);
+    assert!(list.paging.next.is_none());
+}
+
+#[tokio::test]
+async fn update_entry_sends_the_values() {
+    let server = MockServer::start().await;
+    Mock::given(method("PATCH"))
+        .and(path("/mal/v2/anime/10/my_list_status"))
+        .and(body_string(
+            "status=completed&score=8&num_watched_episodes=12&is_rewatching=false&num_times_rewatched=0&start_date=&finish_date=&comments=",
+        ))
+        .respond_with(ResponseTemplate::new(200).set_body_json(json!({
+            "status": "completed",
+            "score": 8,
+            "num_episodes_watched": 12
+        })))
+        .expect(1)
+        .mount(&server)
+        .await;
+
+    mal_queries::update_entry(
+        &common::mal_config(),
+        &common::urls(&server),
+        10,
+        &ListUpdate {
+            status: Status::completed,
+            score: 8,
+            progress: 12,
+            progress_volumes: 0,
+            repeating: false,
+            repeat: 0,
+            start_date: String::new(),
+            finish_date: String::new(),
+            comments: String::new(),
+        },
+        MediaType::ANIME,
+    )
+    .await
+    .unwrap();
+}
+
+#[tokio::test]
+async fn error_responses_are_returned() {
+    let server = MockServer::start().await;
+    Mock::given(method("PATCH"))
+        .respond_with(ResponseTemplate::new(400).set_body_json(json!({
+            "error": "invalid_parameters",
+            "message": "score is invalid"
+        })))
+        .mount(&server)
+        .await;
+
+    let result = mal_queries::update_entry(
+        &common::mal_config(),
+        &common::urls(&server),
+        10,
+        &ListUpdate {
+            status: Status::completed,
+            score: 11,
+            progress: 12,
+            progress_volumes: 0,
+            repeating: false,
+            repeat: 0,
+            start_date: String::new(),
+            finish_date: String::new(),
+            comments: String::new(),
+        },
+        MediaType::ANIME,
+    )
+    .await;
+
+    match result {
+        Err(Error::Api(_, message)) => assert_eq!(message, "invalid_parameters. score is invalid"),
+        other => panic!("Expected an API error, got {:?}", other),
+    }
+}
+
+#[tokio::test]
+async fn unauthorized_is_auth_expired() {
+    let server = MockServer::start().await;
+    Mock::given(method("GET"))
+        .respond_with(ResponseTemplate::new(401).set_body_json(json!({"error": "invalid_token"})))
+        .mount(&server)
+        .await;
+
+    let result = mal_queries::get_list(
+        &common::mal_config(),
+        &common::urls(&server),
+        MediaType::ANIME,
+        1000,
+    )
+    .await;
+
+    assert!(matches!(result, Err(Error::AuthExpired(_))));
+}
+
+#[tokio::test]
+async fn search_returns_candidates() {
+    let server = MockServer::start().await;
+    Mock::given(method("GET"))
+        .and(path("/mal/v2/manga"))
+        .and(query_param("q", "Book A"))
+        .and(query_param("fields", "media_type,start_date"))
+        .respond_with(ResponseTemplate::new(200).set_body_json(json!({
+            "data": [
+                {"node": {"id": 40, "title": "Book A", "media_type": "light_novel", "start_date": "2019"}},
+                {"node": {"id": 41, "title": "Book A Side Stories"}}
+            ],
+            "paging": {}
+        })))
+        .expect(1)
+        .mount(&server)
+        .await;
+
+    let candidates = mal_queries::search(
+        &common::mal_config(),
+        &common::urls(&server),
+        "Book A",
+        MediaType::MANGA,
+    )
+    .await
+    .unwrap();
+
+    assert_eq!(candidates.len(), 2);
+    assert_eq!(candidates[0].id, 40);
+    assert_eq!(candidates[0].media_type.as_deref(), Some("light_novel"));
+    assert_eq!(candidates[0].start_year, Some(2019));
+    assert_eq!(candidates[1].start_year, None);
+}
+
+#[tokio::test]
+async fn rereading_and_volumes_are_synced() {
+    let server = MockServer::start().await;
+    let mut entry = common::mal_entry(30, "Book A", "completed", 8, 0, "2021-10-01T00:00:00+00:00");
+    entry["list_status"] = json!({
+        "status": "completed",
+        "score": 8,
+        "num_chapters_read": 40,
+        "num_volumes_read": 4,
+        "is_rereading": true,
+        "num_times_reread": 2,
+        "updated_at": "2021-10-01T00:00:00+00:00"
+    });
+    Mock::given(method("GET"))
+        .and(path("/mal/v2/users/@me/mangalist"))
+        .respond_with(ResponseTemplate::new(200).set_body_json(common::mal_page(vec![entry], None)))
+        .mount(&server)
+        .await;
+    Mock::given(method("PATCH"))
+        .and(path("/mal/v2/manga/30/my_list_status"))
+        .and(body_string(
+            "status=completed&score=8&num_chapters_read=40&num_volumes_read=4&is_rereading=true&num_times_reread=2&start_date=&finish_date=&comments=",
+        ))
+        .respond_with(ResponseTemplate::new(200).set_body_string("{}"))
+        .expect(1)
+        .mount(&server)
+        .await;
+
+    let list = mal_queries::get_list(
+        &common::mal_config(),
+        &common::urls(&server),
+        MediaType::MANGA,
+        1000,
+    )
+    .await
+    .unwrap();
+    let scores = ScoreConversion::default();
+    let entry = list.data[0].to_list_entry(MediaType::MANGA, &scores);
+    assert_eq!(entry.status, MediaListStatus::Repeating);
+    assert_eq!(entry.repeat, 2);
+    assert_eq!(entry.progress_volumes, Some(4));
+
+    // and sent back the same way
+    let update = ListUpdate::new(&entry.values(), MediaType::MANGA, &scores);
+    mal_queries::update_entry(
+        &common::mal_config(),
+        &common::urls(&server),
+        30,
+        &update,
+        MediaType::MANGA,
+    )
+    .await
+    .unwrap();
+}
+
+#[tokio::test]
+async fn partial_dates_are_synced() {
+    let server = MockServer::start().await;
+    let mut entry = common::mal_entry(
+        10,
+        "Show A",
+        "completed",
+        8,
+        12,
+        "2021-10-01T00:00:00+00:00",
+    );
+    entry["list_status"]["start_date"] = json!("2021-10");
+    entry["list_status"]["finish_date"] = json!("2022");
+    Mock::given(method("GET"))
+        .and(path("/mal/v2/users/@me/animelist"))
+        .respond_with(ResponseTemplate::new(200).set_body_json(common::mal_page(vec![entry], None)))
+        .mount(&server)
+        .await;
+    Mock::given(method("PATCH"))
+        .and(path("/mal/v2/anime/10/my_list_status"))
+        .and(body_string(
+            "status=completed&score=8&num_watched_episodes=12&is_rewatching=false&num_times_rewatched=0&start_date=2021-10&finish_date=2022&comments=",
+        ))
+        .respond_with(ResponseTemplate::new(200).set_body_string("{}"))
+        .expect(1)
+        .mount(&server)
+        .await;
+
+    let list = mal_queries::get_list(
+        &common::mal_config(),
+        &common::urls(&server),
+        MediaType::ANIME,
+        1000,
+    )
+    .await
+    .unwrap();
+    let scores = ScoreConversion::default();
+    let entry = list.data[0].to_list_entry(MediaType::ANIME, &scores);
+    assert_eq!(
+        entry.started_at,
+        FuzzyDate {
+            year: Some(2021),
+            month: Some(10),
+            day: None
+        }
+    );
+    assert_eq!(
+        entry.completed_at,
+        FuzzyDate {
+            year: Some(2022),
+            month: None,
+            day: None
+        }
+    );
+
+    let update = ListUpdate::new(&entry.values(), MediaType::ANIME, &scores);
+    mal_queries::update_entry(
+        &common::mal_config(),
+        &common::urls(&server),
+        10,
+        &update,
+        MediaType::ANIME,
+    )
+    .await
+    .unwrap();
+
+    // MAL can't have a day without a month
+    let date = FuzzyDate {
+        year: Some(2021),
+        month: None,
+        day: Some(3),
+    };
+    assert_eq!(mal_queries::format_date(date), "2021");
+    assert_eq!(mal_queries::format_date(FuzzyDate::default()), "");
+}
+
+#[tokio::test]
+async fn comments_are_synced_as_notes() {
+    let server = MockServer::start().await;
+    let mut entry = common::mal_entry(
+        10,
+        "Show A",
+        "completed",
+        8,
+        12,
+        "2021-10-01T00:00:00+00:00",
+    );
+    entry["list_status"]["comments"] = json!("Watch the dub");
+    let mut cleared = common::mal_entry(
+        20,
+        "Show B",
+        "completed",
+        8,
+        12,
+        "2021-10-01T00:00:00+00:00",
+    );
+    cleared["list_status"]["comments"] = json!("");
+    Mock::given(method("GET"))
+        .and(path("/mal/v2/users/@me/animelist"))
+        .and(query_param(
+            "fields",
+            "list_status{status,score,num_episodes_watched,is_rewatching,num_times_rewatched,start_date,finish_date,comments,updated_at}",
+        ))
+        .respond_with(
+            ResponseTemplate::new(200).set_body_json(common::mal_page(vec![entry, cleared], None)),
+        )
+        .mount(&server)
+        .await;
+
+    let list = mal_queries::get_list(
+        &common::mal_config(),
+        &common::urls(&server),
+        MediaType::ANIME,
+        1000,
+    )
+    .await
+    .unwrap();
+    let scores = ScoreConversion::default();
+    let entries: Vec<_> = list
+        .data
+        .iter()
+        .map(|entry| entry.to_list_entry(MediaType::ANIME, &scores))
+        .collect();
+    assert_eq!(entries[0].notes.as_deref(), Some("Watch the dub"));
+    assert_eq!(entries[1].notes, None);
+
+    // notes that are too long are cut down to what MAL keeps
+    let mut values = entries[0].values();
+    values.notes = Some("a".repeat(mal_queries::MAX_COMMENTS_LENGTH + 10));
+    let update = ListUpdate::new(&values, MediaType::ANIME, &scores);
+    assert_eq!(update.comments.len(), mal_queries::MAX_COMMENTS_LENGTH);
+}
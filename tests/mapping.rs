@@ -0,0 +1,49 @@
+mod common;
+
+use std::path::Path;
+use std::process::Command;
+
+fn run(home: &Path, args: &[&str]) -> String {
+    let output = Command::new(env!("CARGO_BIN_EXE_list-backup"))
+        .args(args)
+        .env("HOME", home)
+        .current_dir(home)
+        .output()
+        .unwrap();
+    assert!(output.status.success());
+    String::from_utf8(output.stdout).unwrap()
+}
+
+#[test]
+fn mappings_can_be_added_and_removed() {
+    let home = common::temp_home("mapping");
+
+    assert!(run(&home, &["mapping", "list"]).starts_with("No mappings"));
+    run(&home, &["mapping", "add", "1", "10"]);
+    // a second override for the same AniList id replaces the first
+    run(&home, &["mapping", "add", "1", "11"]);
+    run(&home, &["mapping", "add", "2", "20", "--block"]);
+    assert_eq!(
+        run(&home, &["mapping", "list"]),
+        "AniList 1 -> MAL 11\nAniList 2 -> MAL 20 (blocked)\n"
+    );
+
+    run(&home, &["mapping", "remove", "1"]);
+    assert_eq!(
+        run(&home, &["mapping", "list"]),
+        "AniList 2 -> MAL 20 (blocked)\n"
+    );
+}
+
+#[test]
+fn ids_need_to_be_numbers() {
+    let home = common::temp_home("mapping-ids");
+    let output = Command::new(env!("CARGO_BIN_EXE_list-backup"))
+        .args(["mapping", "add", "one", "10"])
+        .env("HOME", &home)
+        .current_dir(&home)
+        .output()
+        .unwrap();
+
+    assert_eq!(output.status.code(), Some(2));
+}
@@ -0,0 +1,151 @@
+mod common;
+
+use list_backup::anilist_queries::{AnilistClient, MediaListStatus, MediaType, ScoreFormat};
+use list_backup::mal_queries::{self, MalClient};
+use list_backup::provider::{ListEntry, ListProvider, Service};
+use list_backup::scores::{Rounding, ScoreConversion};
+use serde_json::json;
+use wiremock::matchers::{body_partial_json, body_string_contains, method, path};
+use wiremock::{Mock, MockServer, ResponseTemplate};
+
+/// Goes through everything a provider can do with the one entry on its list, and returns it
+async fn use_provider(provider: &dyn ListProvider, id: u32) -> ListEntry {
+    let user = provider.whoami().await.unwrap();
+    assert_eq!((user.id, user.name), (1, "user".to_string()));
+
+    let mut list = provider.fetch_list(MediaType::ANIME).await.unwrap();
+    assert_eq!(list.len(), 1);
+    let entry = list.remove(0);
+
+    provider
+        .update_entry(MediaType::ANIME, id, &entry.values())
+        .await
+        .unwrap();
+    provider.delete_entry(MediaType::ANIME, id).await.unwrap();
+    entry
+}
+
+#[tokio::test]
+async fn anilist_is_a_provider() {
+    let server = MockServer::start().await;
+    Mock::given(method("POST"))
+        .and(path("/graphql"))
+        .and(body_string_contains("UpdateUser"))
+        .respond_with(
+            ResponseTemplate::new(200)
+                .set_body_json(json!({"data": {"UpdateUser": {"id": 1, "name": "user"}}})),
+        )
+        .mount(&server)
+        .await;
+    Mock::given(method("POST"))
+        .and(path("/graphql"))
+        .and(body_string_contains("MediaListCollection"))
+        .respond_with(
+            ResponseTemplate::new(200).set_body_json(common::anilist_list(vec![
+                common::anilist_entry(1, Some(10), "Show A", "CURRENT", 7.0, 3, 1633132800),
+            ])),
+        )
+        .mount(&server)
+        .await;
+    Mock::given(method("POST"))
+        .and(path("/graphql"))
+        .and(body_string_contains("SaveMediaListEntry"))
+        .and(body_partial_json(json!({
+            "variables": {"media_id": 1, "status": "CURRENT", "score": 7.0, "progress": 3}
+        })))
+        .respond_with(
+            ResponseTemplate::new(200)
+                .set_body_json(json!({"data": {"SaveMediaListEntry": {"id": 5}}})),
+        )
+        .expect(1)
+        .mount(&server)
+        .await;
+    // deleting looks up the list entry's id first
+    Mock::given(method("POST"))
+        .and(path("/graphql"))
+        .and(body_string_contains("MediaList(userId"))
+        .respond_with(
+            ResponseTemplate::new(200).set_body_json(json!({"data": {"MediaList": {"id": 5}}})),
+        )
+        .mount(&server)
+        .await;
+    Mock::given(method("POST"))
+        .and(path("/graphql"))
+        .and(body_string_contains("DeleteMediaListEntry"))
+        .and(body_partial_json(json!({"variables": {"id": 5}})))
+        .respond_with(
+            ResponseTemplate::new(200)
+                .set_body_json(json!({"data": {"DeleteMediaListEntry": {"deleted": true}}})),
+        )
+        .expect(1)
+        .mount(&server)
+        .await;
+
+    let config = common::anilist_config();
+    let urls = common::urls(&server);
+    let anilist = AnilistClient {
+        config: &config,
+        urls: &urls,
+    };
+    assert_eq!(anilist.service(), Service::Anilist);
+    let entry = use_provider(&anilist, 1).await;
+    assert_eq!(entry.anilist_id, Some(1));
+    assert_eq!(entry.mal_id, Some(10));
+    assert_eq!(entry.title, "Show A");
+    assert_eq!(entry.status, MediaListStatus::Current);
+    assert_eq!((entry.score, entry.progress), (7.0, 3));
+}
+
+#[tokio::test]
+async fn mal_is_a_provider() {
+    let server = MockServer::start().await;
+    common::mount_mal_user(&server).await;
+    Mock::given(method("GET"))
+        .and(path("/mal/v2/users/@me/animelist"))
+        .respond_with(ResponseTemplate::new(200).set_body_json(common::mal_page(
+            vec![common::mal_entry(
+                10,
+                "Show A",
+                "watching",
+                7,
+                3,
+                "2021-10-01T00:00:00+00:00",
+            )],
+            None,
+        )))
+        .mount(&server)
+        .await;
+    Mock::given(method("PATCH"))
+        .and(path("/mal/v2/anime/10/my_list_status"))
+        .respond_with(ResponseTemplate::new(200).set_body_string("{}"))
+        .expect(1)
+        .mount(&server)
+        .await;
+    Mock::given(method("DELETE"))
+        .and(path("/mal/v2/anime/10/my_list_status"))
+        .respond_with(ResponseTemplate::new(200).set_body_string("[]"))
+        .expect(1)
+        .mount(&server)
+        .await;
+
+    let config = common::mal_config();
+    let urls = common::urls(&server);
+    let mal = MalClient {
+        config: &config,
+        urls: &urls,
+        page_size: mal_queries::MAX_PAGE_SIZE,
+        scores: ScoreConversion {
+            format: ScoreFormat::Point10,
+            rounding: Rounding::Down,
+        },
+    };
+    assert_eq!(mal.service(), Service::Mal);
+    // the same entry as AniList's, apart from what only AniList has
+    let entry = use_provider(&mal, 10).await;
+    assert_eq!(entry.anilist_id, None);
+    assert_eq!(entry.mal_id, Some(10));
+    assert_eq!(entry.title, "Show A");
+    assert_eq!(entry.status, MediaListStatus::Current);
+    assert_eq!((entry.score, entry.progress), (7.0, 3));
+    assert_eq!(entry.mal_score, Some(7));
+}
@@ -0,0 +1,215 @@
+mod common;
+
+use std::fs;
+
+use list_backup::anilist_queries::{FuzzyDate, MediaListStatus, ScoreFormat};
+use list_backup::provider::{ListEntry, Service};
+use list_backup::restore;
+use list_backup::save_to_file::{self, BackupToml};
+use list_backup::scores::{Rounding, ScoreConversion};
+use list_backup::sync::Action;
+
+/// Reads in a TOML backup with the given schema version and status sections
+fn backup(name: &str, schema_version: u32, entries: &str) -> BackupToml {
+    let contents = format!(
+        r#"
+schema_version = {}
+
+[user_section]
+user_id = 1
+username = "user"
+list_type = "ANIME"
+total_anime = 1
+watching = 0
+completed = 1
+on_hold = 0
+dropped = 0
+planning = 0
+rewatching = 0
+{}
+"#,
+        schema_version, entries
+    );
+    let path = common::temp_home(name).join("backup.toml");
+    fs::write(&path, contents).unwrap();
+    save_to_file::read_backup(&path).unwrap()
+}
+
+/// What a schema version 1 backup has for an entry
+const SHOW_A: &str = r#"
+[[completed]]
+title = "Show A"
+id = 1
+id_mal = 10
+episodes = 12
+format = "Tv"
+status = "Completed"
+score = 8.0
+progress = 12
+"#;
+
+/// The entry on either service, with everything a version 1 backup doesn't have filled in
+fn live_entry(progress: u32) -> ListEntry {
+    let mut entry = ListEntry::new(
+        "Show A".to_string(),
+        MediaListStatus::Completed,
+        8.0,
+        progress,
+    );
+    entry.anilist_id = Some(1);
+    entry.mal_id = Some(10);
+    entry.repeat = 2;
+    entry.notes = Some("Watch the dub".to_string());
+    entry.started_at = FuzzyDate {
+        year: Some(2021),
+        month: Some(10),
+        day: None,
+    };
+    entry
+}
+
+#[test]
+fn old_backups_leave_newer_fields_alone() {
+    let backup = backup("restore-old", 1, SHOW_A);
+    let scores = ScoreConversion::default();
+
+    for target in [Service::Anilist, Service::Mal] {
+        // nothing the old backup has is different
+        let plan = restore::build_plan(&backup, &[live_entry(12)], target, &scores);
+        assert!(plan.changes.is_empty(), "{:?}", target);
+
+        let plan = restore::build_plan(&backup, &[live_entry(3)], target, &scores);
+        let after = &plan.changes[0].after;
+        assert_eq!(after.progress, 12);
+        assert_eq!(after.repeat, 2);
+        assert_eq!(after.notes.as_deref(), Some("Watch the dub"));
+        assert_eq!(after.started_at, live_entry(3).started_at);
+    }
+}
+
+#[test]
+fn scores_are_converted_from_the_backups_format() {
+    let entry = SHOW_A.replace("score = 8.0", "score = 85.0");
+    // straight after the user section's other fields
+    let backup = backup(
+        "restore-scores",
+        4,
+        &format!("score_format = \"POINT_100\"\n{}", entry),
+    );
+    assert_eq!(
+        backup.user_section.score_format,
+        Some(ScoreFormat::Point100)
+    );
+
+    // the user has switched to 10 point scores since
+    let point_10 = ScoreConversion {
+        format: ScoreFormat::Point10Decimal,
+        rounding: Rounding::Down,
+    };
+    let plan = restore::build_plan(&backup, &[], Service::Anilist, &point_10);
+    assert_eq!(plan.changes[0].after.score, 8.5);
+
+    // an entry that already has the converted score is left alone
+    let mut live = ListEntry::new("Show A".to_string(), MediaListStatus::Completed, 8.5, 12);
+    live.anilist_id = Some(1);
+    let plan = restore::build_plan(&backup, &[live], Service::Anilist, &point_10);
+    assert!(plan.changes.is_empty());
+}
+
+#[test]
+fn entries_are_created_or_updated() {
+    let backup = common::backup(
+        &common::temp_home("restore-actions"),
+        "backup",
+        &[
+            common::list_entry(1, Some(10), "Show A", MediaListStatus::Completed),
+            common::list_entry(2, Some(20), "Show B", MediaListStatus::Completed),
+        ],
+    );
+    let mut show_a = common::list_entry(1, Some(10), "Show A", MediaListStatus::Current);
+    show_a.progress = 3;
+
+    let plan = restore::build_plan(
+        &backup,
+        &[show_a],
+        Service::Anilist,
+        &ScoreConversion::default(),
+    );
+    let actions: Vec<_> = plan
+        .changes
+        .iter()
+        .map(|change| (change.anilist_id, change.action))
+        .collect();
+    assert_eq!(actions, [(1, Action::Update), (2, Action::Create)]);
+    assert_eq!(plan.changes[0].before.as_ref().unwrap().progress, 3);
+    assert_eq!(plan.changes[0].after.progress, 12);
+}
+
+#[test]
+fn entries_without_a_mal_id_are_skipped_on_mal() {
+    let backup = common::backup(
+        &common::temp_home("restore-mal"),
+        "backup",
+        &[
+            common::list_entry(1, Some(10), "Show A", MediaListStatus::Completed),
+            common::list_entry(2, Some(20), "Show B", MediaListStatus::Completed),
+            common::list_entry(3, None, "Show C", MediaListStatus::Completed),
+        ],
+    );
+    // MAL's entries are matched on the MAL id
+    let mut show_a = common::list_entry(1, Some(10), "Show A", MediaListStatus::Current);
+    show_a.anilist_id = None;
+    show_a.progress = 3;
+
+    let plan = restore::build_plan(
+        &backup,
+        &[show_a],
+        Service::Mal,
+        &ScoreConversion::default(),
+    );
+    let actions: Vec<_> = plan
+        .changes
+        .iter()
+        .map(|change| (change.mal_id, change.action))
+        .collect();
+    assert_eq!(
+        actions,
+        [(Some(10), Action::Update), (Some(20), Action::Create)]
+    );
+}
+
+#[test]
+fn fields_only_anilist_has_are_restored_there() {
+    let mut show_a = common::list_entry(1, Some(10), "Show A", MediaListStatus::Completed);
+    show_a.private = true;
+    show_a.hidden_from_status_lists = true;
+    show_a.custom_lists = vec!["Favourites".to_string()];
+    show_a.advanced_scores.insert("Story".to_string(), 9.0);
+    let backup = common::backup(
+        &common::temp_home("restore-anilist-fields"),
+        "backup",
+        &[show_a.clone()],
+    );
+    let scores = ScoreConversion::default();
+
+    let live = common::list_entry(1, Some(10), "Show A", MediaListStatus::Completed);
+    let plan = restore::build_plan(
+        &backup,
+        std::slice::from_ref(&live),
+        Service::Anilist,
+        &scores,
+    );
+    let after = &plan.changes[0].after;
+    assert_eq!(after.private, Some(true));
+    assert_eq!(after.hidden_from_status_lists, Some(true));
+    assert_eq!(after.custom_lists, Some(vec!["Favourites".to_string()]));
+    assert_eq!(after.advanced_scores, Some(show_a.advanced_scores.clone()));
+
+    // nothing to do once they're on AniList
+    let plan = restore::build_plan(&backup, &[show_a], Service::Anilist, &scores);
+    assert!(plan.changes.is_empty());
+
+    // and MAL doesn't have them
+    let plan = restore::build_plan(&backup, &[live], Service::Mal, &scores);
+    assert!(plan.changes.is_empty());
+}
@@ -0,0 +1,132 @@
+use list_backup::anilist_queries::ScoreFormat;
+use list_backup::scores::{Rounding, ScoreConversion};
+
+const FORMATS: [ScoreFormat; 5] = [
+    ScoreFormat::Point100,
+    ScoreFormat::Point10Decimal,
+    ScoreFormat::Point10,
+    ScoreFormat::Point5,
+    ScoreFormat::Point3,
+];
+
+const ROUNDINGS: [Rounding; 3] = [Rounding::Down, Rounding::Nearest, Rounding::Up];
+
+/// Every score other than no score
+fn every_score(format: ScoreFormat) -> Vec<f32> {
+    match format {
+        ScoreFormat::Point100 => (1..=100).map(|score| score as f32).collect(),
+        ScoreFormat::Point10Decimal => (1..=100).map(|score| score as f32 / 10.0).collect(),
+        ScoreFormat::Point10 => (1..=10).map(|score| score as f32).collect(),
+        ScoreFormat::Point5 => (1..=5).map(|score| score as f32).collect(),
+        ScoreFormat::Point3 => (1..=3).map(|score| score as f32).collect(),
+    }
+}
+
+fn conversion(format: ScoreFormat, rounding: Rounding) -> ScoreConversion {
+    ScoreConversion { format, rounding }
+}
+
+#[test]
+fn scores_are_converted_to_mal() {
+    let down = |format| conversion(format, Rounding::Down);
+    assert_eq!(down(ScoreFormat::Point100).to_mal(85.0), 8);
+    assert_eq!(down(ScoreFormat::Point10Decimal).to_mal(7.5), 7);
+    assert_eq!(down(ScoreFormat::Point10).to_mal(7.0), 7);
+    assert_eq!(down(ScoreFormat::Point5).to_mal(4.0), 8);
+    assert_eq!(down(ScoreFormat::Point3).to_mal(1.0), 3);
+    assert_eq!(down(ScoreFormat::Point3).to_mal(3.0), 8);
+
+    let nearest = |format| conversion(format, Rounding::Nearest);
+    assert_eq!(nearest(ScoreFormat::Point100).to_mal(85.0), 9);
+    assert_eq!(nearest(ScoreFormat::Point100).to_mal(84.0), 8);
+    assert_eq!(nearest(ScoreFormat::Point3).to_mal(3.0), 9);
+
+    let up = |format| conversion(format, Rounding::Up);
+    assert_eq!(up(ScoreFormat::Point10Decimal).to_mal(7.1), 8);
+    assert_eq!(up(ScoreFormat::Point10Decimal).to_mal(7.0), 7);
+}
+
+#[test]
+fn scores_are_converted_from_mal() {
+    let down = |format| conversion(format, Rounding::Down);
+    assert_eq!(down(ScoreFormat::Point100).from_mal(7), 70.0);
+    assert_eq!(down(ScoreFormat::Point10Decimal).from_mal(7), 7.0);
+    assert_eq!(down(ScoreFormat::Point5).from_mal(7), 3.0);
+    assert_eq!(down(ScoreFormat::Point3).from_mal(7), 2.0);
+    assert_eq!(down(ScoreFormat::Point3).from_mal(10), 3.0);
+
+    let nearest = |format| conversion(format, Rounding::Nearest);
+    assert_eq!(nearest(ScoreFormat::Point5).from_mal(7), 4.0);
+    assert_eq!(nearest(ScoreFormat::Point3).from_mal(1), 1.0);
+}
+
+#[test]
+fn no_score_stays_no_score() {
+    for format in FORMATS {
+        let scores = conversion(format, Rounding::Down);
+        assert_eq!(scores.to_mal(0.0), 0);
+        assert_eq!(scores.from_mal(0), 0.0);
+        // the lowest score isn't rounded away to nothing
+        assert!(scores.to_mal(1.0) >= 1);
+    }
+}
+
+#[test]
+fn scores_round_trip_through_mal() {
+    for format in FORMATS {
+        for rounding in ROUNDINGS {
+            let scores = conversion(format, rounding);
+            for score in every_score(format) {
+                let mal = scores.to_mal(score);
+                let back = scores.from_mal(mal);
+                // otherwise a synced entry would look changed again on the next run
+                assert_eq!(
+                    scores.to_mal(back),
+                    mal,
+                    "{:?} {:?} {}",
+                    format,
+                    rounding,
+                    score
+                );
+                // MAL can hold every score these formats have, so nothing should be lost
+                if matches!(
+                    format,
+                    ScoreFormat::Point10 | ScoreFormat::Point5 | ScoreFormat::Point3
+                ) {
+                    assert_eq!(back, score, "{:?} {:?} {}", format, rounding, score);
+                }
+            }
+        }
+    }
+}
+
+#[test]
+fn scores_are_converted_between_anilist_formats() {
+    let down = |format| conversion(format, Rounding::Down);
+    assert_eq!(
+        down(ScoreFormat::Point5).from_format(85.0, ScoreFormat::Point100),
+        4.0
+    );
+    assert_eq!(
+        down(ScoreFormat::Point10Decimal).from_format(85.0, ScoreFormat::Point100),
+        8.5
+    );
+    assert_eq!(
+        down(ScoreFormat::Point100).from_format(3.0, ScoreFormat::Point3),
+        85.0
+    );
+    assert_eq!(
+        down(ScoreFormat::Point3).from_format(8.5, ScoreFormat::Point10Decimal),
+        3.0
+    );
+    assert_eq!(
+        down(ScoreFormat::Point10).from_format(0.0, ScoreFormat::Point100),
+        0.0
+    );
+    // a score in the same format is left as it is
+    for format in FORMATS {
+        for score in every_score(format) {
+            assert_eq!(down(format).from_format(score, format), score);
+        }
+    }
+}
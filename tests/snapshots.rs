@@ -0,0 +1,187 @@
+mod common;
+
+use std::fs;
+use std::path::Path;
+use std::process::Command;
+
+use list_backup::anilist_queries::MediaType;
+use list_backup::snapshots::{self, Retention};
+
+/// Newest first. Sunday and Friday of the same week, the week before and a month before that
+const SNAPSHOTS: [&str; 5] = [
+    "anime-backup-2021-10-03T120000Z.toml",
+    "anime-backup-2021-10-03T080000Z.toml",
+    "anime-backup-2021-10-01T120000Z.toml",
+    "anime-backup-2021-09-20T120000Z.toml",
+    "anime-backup-2021-08-15T120000Z.toml",
+];
+
+fn create_files(dir: &Path, names: &[&str]) {
+    for name in names {
+        fs::write(dir.join(name), "").unwrap();
+    }
+}
+
+fn file_names(snapshots: &[snapshots::Snapshot]) -> Vec<String> {
+    snapshots
+        .iter()
+        .map(|snapshot| {
+            let name = snapshot.path.file_name().unwrap();
+            name.to_string_lossy().into_owned()
+        })
+        .collect()
+}
+
+/// The snapshots left after pruning `SNAPSHOTS` with `retention`
+fn prune(name: &str, retention: Retention) -> Vec<String> {
+    let dir = common::temp_home(name);
+    create_files(&dir, &SNAPSHOTS);
+    snapshots::prune(&dir, MediaType::ANIME, &retention).unwrap();
+    file_names(&snapshots::list_snapshots(&dir, Some(MediaType::ANIME)))
+}
+
+#[test]
+fn snapshots_are_found_by_name() {
+    let dir = common::temp_home("snapshots-names");
+    create_files(
+        &dir,
+        &[
+            "anime-backup-2021-09-30T120000Z.toml",
+            // two more taken in the same second
+            "anime-backup-2021-09-30T120000Z.1.json",
+            "anime-backup-2021-09-30T120000Z.2.csv",
+            "manga-backup-2021-10-01T000000Z.xml",
+            // not something a backup is written as
+            "anime-backup-2021-10-02T000000Z.txt",
+            "anime-backup-2021-10-02T000000Z.x.toml",
+            "anime-backup-yesterday.toml",
+            "notes.toml",
+        ],
+    );
+
+    let anime = snapshots::list_snapshots(&dir, Some(MediaType::ANIME));
+    assert_eq!(
+        file_names(&anime),
+        vec![
+            "anime-backup-2021-09-30T120000Z.2.csv",
+            "anime-backup-2021-09-30T120000Z.1.json",
+            "anime-backup-2021-09-30T120000Z.toml",
+        ]
+    );
+    assert_eq!(anime[0].version, 2);
+    assert_eq!(anime[2].version, 0);
+
+    let all = snapshots::list_snapshots(&dir, None);
+    assert_eq!(all.len(), 4);
+    assert_eq!(all[0].list_type, MediaType::MANGA);
+}
+
+#[test]
+fn retention_keeps_the_newest_of_each_period() {
+    let last = prune(
+        "snapshots-last",
+        Retention {
+            keep_last: Some(2),
+            ..Retention::default()
+        },
+    );
+    assert_eq!(last, SNAPSHOTS[..2]);
+
+    let daily = prune(
+        "snapshots-daily",
+        Retention {
+            keep_daily: Some(2),
+            ..Retention::default()
+        },
+    );
+    assert_eq!(daily, [SNAPSHOTS[0], SNAPSHOTS[2]]);
+
+    let weekly = prune(
+        "snapshots-weekly",
+        Retention {
+            keep_weekly: Some(2),
+            ..Retention::default()
+        },
+    );
+    assert_eq!(weekly, [SNAPSHOTS[0], SNAPSHOTS[3]]);
+
+    let monthly = prune(
+        "snapshots-monthly",
+        Retention {
+            keep_monthly: Some(3),
+            ..Retention::default()
+        },
+    );
+    assert_eq!(monthly, [SNAPSHOTS[0], SNAPSHOTS[3], SNAPSHOTS[4]]);
+
+    // anything kept by one of the rules is kept
+    let combined = prune(
+        "snapshots-combined",
+        Retention {
+            keep_last: Some(2),
+            keep_monthly: Some(2),
+            ..Retention::default()
+        },
+    );
+    assert_eq!(combined, [SNAPSHOTS[0], SNAPSHOTS[1], SNAPSHOTS[3]]);
+}
+
+#[test]
+fn the_newest_snapshot_is_always_kept() {
+    let last = prune(
+        "snapshots-none",
+        Retention {
+            keep_last: Some(0),
+            ..Retention::default()
+        },
+    );
+    assert_eq!(last, SNAPSHOTS[..1]);
+
+    let daily = prune(
+        "snapshots-no-days",
+        Retention {
+            keep_daily: Some(0),
+            ..Retention::default()
+        },
+    );
+    assert_eq!(daily, SNAPSHOTS[..1]);
+
+    // and the command doesn't accept keeping none
+    let home = common::temp_home("snapshots-keep-0");
+    let output = Command::new(env!("CARGO_BIN_EXE_list-backup"))
+        .args(["backup", "anime", "--keep", "0"])
+        .env("HOME", &home)
+        .output()
+        .unwrap();
+    assert_eq!(output.status.code(), Some(2));
+    assert!(String::from_utf8_lossy(&output.stderr).contains("above 0"));
+}
+
+#[test]
+fn each_format_is_pruned_separately() {
+    let dir = common::temp_home("snapshots-formats");
+    create_files(
+        &dir,
+        &[
+            "anime-backup-2021-10-03T120000Z.csv",
+            "anime-backup-2021-10-03T080000Z.toml",
+            "anime-backup-2021-10-02T120000Z.toml",
+            "anime-backup-2021-10-01T120000Z.xml",
+        ],
+    );
+    let retention = Retention {
+        keep_daily: Some(1),
+        ..Retention::default()
+    };
+    snapshots::prune(&dir, MediaType::ANIME, &retention).unwrap();
+
+    // the CSV export taken since doesn't push out the day's TOML backup
+    assert_eq!(
+        file_names(&snapshots::list_snapshots(&dir, Some(MediaType::ANIME))),
+        vec![
+            "anime-backup-2021-10-03T120000Z.csv",
+            "anime-backup-2021-10-03T080000Z.toml",
+            "anime-backup-2021-10-01T120000Z.xml",
+        ]
+    );
+}
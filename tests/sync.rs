@@ -0,0 +1,309 @@
+mod common;
+
+use list_backup::anilist_queries::{
+    AnilistClient, FuzzyDate, MediaListStatus, MediaType, ScoreFormat,
+};
+use list_backup::mal_queries;
+use list_backup::mappings::Mappings;
+use list_backup::provider::{ListEntry, Service};
+use list_backup::scores::{Rounding, ScoreConversion};
+use list_backup::sync::{self, Direction, Plan};
+use wiremock::MockServer;
+
+const POINT_100: ScoreConversion = ScoreConversion {
+    format: ScoreFormat::Point100,
+    rounding: Rounding::Down,
+};
+
+fn anilist_entry(score: f32, progress: u32, updated_at: i64) -> ListEntry {
+    let mut entry = ListEntry::new(
+        "Show A".to_string(),
+        MediaListStatus::Current,
+        score,
+        progress,
+    );
+    entry.anilist_id = Some(1);
+    entry.mal_id = Some(10);
+    entry.updated_at = Some(updated_at);
+    entry
+}
+
+/// The same entry as MAL has it, which is what `to_list_entry` would give
+fn mal_entry(score: f32, progress: u32, updated_at: i64) -> ListEntry {
+    let mut entry = anilist_entry(score, progress, updated_at);
+    entry.anilist_id = None;
+    entry
+}
+
+async fn build_plan(
+    anilist_entries: &[ListEntry],
+    mal_entries: &[ListEntry],
+    direction: Direction,
+    scores: &ScoreConversion,
+) -> Plan {
+    let server = MockServer::start().await;
+    let config = common::anilist_config();
+    let urls = common::urls(&server);
+    let anilist = AnilistClient {
+        config: &config,
+        urls: &urls,
+    };
+    sync::build_plan(
+        &anilist,
+        &Mappings::default(),
+        anilist_entries,
+        mal_entries,
+        MediaType::ANIME,
+        direction,
+        scores,
+    )
+    .await
+    .unwrap()
+}
+
+#[tokio::test]
+async fn scores_mal_cant_hold_are_kept_on_anilist() {
+    // MAL has the 85 as 8, and was updated last because of the progress
+    let plan = build_plan(
+        &[anilist_entry(85.0, 3, 100)],
+        &[mal_entry(80.0, 4, 200)],
+        Direction::Both,
+        &POINT_100,
+    )
+    .await;
+    assert_eq!(plan.changes.len(), 1);
+    let change = &plan.changes[0];
+    assert_eq!(change.target, Service::Anilist);
+    assert_eq!(change.after.progress, 4);
+    assert_eq!(change.after.score, 85.0);
+
+    // a score that really changed on MAL still wins
+    let plan = build_plan(
+        &[anilist_entry(85.0, 3, 100)],
+        &[mal_entry(60.0, 4, 200)],
+        Direction::Both,
+        &POINT_100,
+    )
+    .await;
+    assert_eq!(plan.changes[0].after.score, 60.0);
+
+    // and nothing changes when the scores only differ by what MAL can hold
+    let plan = build_plan(
+        &[anilist_entry(85.0, 4, 100)],
+        &[mal_entry(80.0, 4, 200)],
+        Direction::Both,
+        &POINT_100,
+    )
+    .await;
+    assert!(plan.changes.is_empty());
+}
+
+#[tokio::test]
+async fn notes_mal_cut_off_are_kept_on_anilist() {
+    let notes = "a".repeat(mal_queries::MAX_COMMENTS_LENGTH + 10);
+    let mut anilist = anilist_entry(85.0, 3, 100);
+    anilist.notes = Some(notes.clone());
+    let mut mal = mal_entry(80.0, 4, 200);
+    mal.notes = Some(mal_queries::mal_comments(Some(&notes)));
+
+    let plan = build_plan(
+        &[anilist.clone()],
+        &[mal.clone()],
+        Direction::Both,
+        &POINT_100,
+    )
+    .await;
+    assert_eq!(plan.changes[0].target, Service::Anilist);
+    assert_eq!(plan.changes[0].after.notes, Some(notes.clone()));
+
+    // the cut off notes count as the same, so there's nothing to push to MAL every run
+    mal.progress = 3;
+    let plan = build_plan(&[anilist], &[mal], Direction::AnilistToMal, &POINT_100).await;
+    assert!(plan.changes.is_empty());
+}
+
+#[tokio::test]
+async fn dates_mal_cant_hold_are_kept_on_anilist() {
+    // MAL can't have a day without a month, so it only has the year
+    let started_at = FuzzyDate {
+        year: Some(2021),
+        month: None,
+        day: Some(3),
+    };
+    let mut anilist = anilist_entry(85.0, 3, 100);
+    anilist.started_at = started_at;
+    let mut mal = mal_entry(80.0, 4, 200);
+    mal.started_at = FuzzyDate {
+        year: Some(2021),
+        month: None,
+        day: None,
+    };
+
+    let plan = build_plan(&[anilist], &[mal], Direction::Both, &POINT_100).await;
+    assert_eq!(plan.changes[0].target, Service::Anilist);
+    assert_eq!(plan.changes[0].after.started_at, started_at);
+}
+
+/// Whether MAL's copy of the entry counts as different once `change` is made to it
+fn mal_differs(change: impl Fn(&mut ListEntry)) -> bool {
+    let anilist = anilist_entry(85.0, 4, 100);
+    let mut mal = mal_entry(85.0, 4, 200);
+    change(&mut mal);
+    sync::differs(&anilist.values(), &mal.values(), &POINT_100)
+}
+
+#[tokio::test]
+async fn repeats_are_synced_once() {
+    assert!(!mal_differs(|_| {}));
+    assert!(mal_differs(|mal| mal.repeat = 1));
+    assert!(mal_differs(|mal| mal.status = MediaListStatus::Repeating));
+
+    let mut anilist = anilist_entry(85.0, 4, 100);
+    anilist.repeat = 1;
+    let mut mal = mal_entry(80.0, 4, 200);
+    let plan = build_plan(
+        &[anilist.clone()],
+        &[mal.clone()],
+        Direction::AnilistToMal,
+        &POINT_100,
+    )
+    .await;
+    assert_eq!(plan.changes.len(), 1);
+    assert_eq!(plan.changes[0].target, Service::Mal);
+    assert_eq!(plan.changes[0].after.repeat, 1);
+
+    // once MAL has it, there's nothing left to do
+    mal.repeat = 1;
+    for direction in [
+        Direction::AnilistToMal,
+        Direction::MalToAnilist,
+        Direction::Both,
+    ] {
+        let plan = build_plan(&[anilist.clone()], &[mal.clone()], direction, &POINT_100).await;
+        assert!(plan.changes.is_empty(), "{:?}", direction);
+    }
+}
+
+#[tokio::test]
+async fn volumes_are_synced_once() {
+    // MAL doesn't have volumes for anime, so missing and 0 are the same
+    assert!(!mal_differs(|mal| mal.progress_volumes = Some(0)));
+    assert!(mal_differs(|mal| mal.progress_volumes = Some(2)));
+
+    let anilist = anilist_entry(85.0, 4, 100);
+    let mut mal = mal_entry(80.0, 4, 200);
+    mal.progress_volumes = Some(0);
+    for direction in [
+        Direction::AnilistToMal,
+        Direction::MalToAnilist,
+        Direction::Both,
+    ] {
+        let plan = build_plan(
+            std::slice::from_ref(&anilist),
+            &[mal.clone()],
+            direction,
+            &POINT_100,
+        )
+        .await;
+        assert!(plan.changes.is_empty(), "{:?}", direction);
+    }
+
+    mal.progress_volumes = Some(2);
+    let plan = build_plan(&[anilist], &[mal], Direction::MalToAnilist, &POINT_100).await;
+    assert_eq!(plan.changes[0].target, Service::Anilist);
+    assert_eq!(plan.changes[0].after.progress_volumes, Some(2));
+    assert_eq!(plan.changes[0].after.score, 85.0);
+}
+
+#[test]
+fn dates_are_compared_as_mal_has_them() {
+    // a month without a year can't be written to MAL
+    assert!(!mal_differs(|mal| {
+        mal.started_at = FuzzyDate {
+            year: None,
+            month: Some(10),
+            day: None,
+        }
+    }));
+    assert!(mal_differs(|mal| {
+        mal.completed_at = FuzzyDate {
+            year: Some(2021),
+            month: Some(10),
+            day: None,
+        }
+    }));
+}
+
+#[test]
+fn notes_are_compared_up_to_where_mal_cuts_them_off() {
+    assert!(mal_differs(
+        |mal| mal.notes = Some("Watch the dub".to_string())
+    ));
+
+    let notes = "a".repeat(mal_queries::MAX_COMMENTS_LENGTH);
+    let mut anilist = anilist_entry(85.0, 4, 100);
+    anilist.notes = Some(format!("{}b", notes));
+    let mut mal = mal_entry(85.0, 4, 200);
+    mal.notes = Some(notes.clone());
+    assert!(!sync::differs(&anilist.values(), &mal.values(), &POINT_100));
+
+    mal.notes = Some(format!("{}c", &notes[1..]));
+    assert!(sync::differs(&anilist.values(), &mal.values(), &POINT_100));
+}
+
+#[tokio::test]
+async fn scores_are_compared_with_what_mal_has() {
+    let stars = ScoreConversion {
+        format: ScoreFormat::Point5,
+        rounding: Rounding::Down,
+    };
+    let smileys = ScoreConversion {
+        format: ScoreFormat::Point3,
+        rounding: Rounding::Down,
+    };
+    // MAL's 9 is 4 stars, but 4 stars is 8 on MAL
+    let mut mal = mal_entry(stars.from_mal(9), 4, 200);
+    mal.mal_score = Some(9);
+    let plan = build_plan(
+        &[anilist_entry(4.0, 4, 100)],
+        &[mal.clone()],
+        Direction::AnilistToMal,
+        &stars,
+    )
+    .await;
+    assert_eq!(plan.changes.len(), 1);
+    let before = plan.changes[0].before.as_ref().unwrap();
+    assert_eq!(before.mal_score, Some(9));
+    assert_eq!(plan.changes[0].after.score, 4.0);
+
+    mal.mal_score = Some(8);
+    let plan = build_plan(
+        &[anilist_entry(4.0, 4, 100)],
+        &[mal],
+        Direction::AnilistToMal,
+        &stars,
+    )
+    .await;
+    assert!(plan.changes.is_empty());
+
+    // a neutral smiley is 6 on MAL, and 5 would come back as one too
+    let mut mal = mal_entry(smileys.from_mal(5), 4, 200);
+    mal.mal_score = Some(5);
+    assert_eq!(mal.score, 2.0);
+    assert!(sync::differs(
+        &anilist_entry(2.0, 4, 100).values(),
+        &mal.values(),
+        &smileys
+    ));
+
+    // AniList only gets the converted score, and keeps its own when MAL's is the same
+    let plan = build_plan(
+        &[anilist_entry(2.0, 3, 100)],
+        &[mal],
+        Direction::MalToAnilist,
+        &smileys,
+    )
+    .await;
+    assert_eq!(plan.changes[0].after.score, 2.0);
+    assert_eq!(plan.changes[0].after.mal_score, None);
+}
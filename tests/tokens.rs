@@ -0,0 +1,216 @@
+mod common;
+
+use std::env;
+
+use list_backup::anilist_queries::ScoreFormat;
+use list_backup::config::{self, TomlConfig};
+use serde_json::json;
+use wiremock::matchers::{body_partial_json, body_string_contains, header, method, path};
+use wiremock::{Mock, MockServer, ResponseTemplate};
+
+// one test, because it changes environment variables for the whole process
+#[tokio::test]
+async fn tokens_are_exchanged_and_saved() {
+    let server = MockServer::start().await;
+    let urls = common::urls(&server);
+    env::set_var("HOME", common::temp_home("tokens"));
+    env::set_var("ANILIST_SECRET", "anilist-secret");
+    env::set_var("MAL_CLIENT_ID", "mal-client-id");
+    env::set_var("MAL_SECRET", "mal-secret");
+
+    // logging in to AniList
+    Mock::given(method("POST"))
+        .and(path("/anilist/oauth/token"))
+        .and(body_partial_json(json!({
+            "grant_type": "authorization_code",
+            "client_secret": "anilist-secret",
+            "code": "anilist-code"
+        })))
+        .respond_with(
+            ResponseTemplate::new(200).set_body_json(common::token_response("anilist-token")),
+        )
+        .expect(1)
+        .mount(&server)
+        .await;
+    Mock::given(method("POST"))
+        .and(path("/graphql"))
+        .and(body_string_contains("UpdateUser"))
+        .respond_with(ResponseTemplate::new(200).set_body_json(json!({
+            "data": {"UpdateUser": {"id": 1, "name": "user"}}
+        })))
+        .expect(1)
+        .mount(&server)
+        .await;
+
+    common::mount_score_format(&server, "POINT_100").await;
+
+    config::get_anilist_token(&urls, "anilist-code")
+        .await
+        .unwrap();
+    let anilist = config::load_config().unwrap().anilist.unwrap();
+    assert_eq!(anilist.access_token, "anilist-token");
+    assert_eq!(anilist.user_name, "user");
+    assert_eq!(anilist.score_format, Some(ScoreFormat::Point100));
+
+    // logging in to MAL
+    Mock::given(method("POST"))
+        .and(path("/mal/oauth2/token"))
+        .and(body_string_contains(
+            "grant_type=authorization_code&code=mal-code&code_verifier=pkce",
+        ))
+        .respond_with(ResponseTemplate::new(200).set_body_json(common::token_response("mal-token")))
+        .expect(1)
+        .mount(&server)
+        .await;
+
+    config::get_mal_token(&urls, "mal-code", "pkce")
+        .await
+        .unwrap();
+    let mal = config::load_config().unwrap().myanimelist.unwrap();
+    assert_eq!(mal.access_token, "mal-token");
+
+    // refreshing an expired AniList token, with the URLs from the config file this time
+    Mock::given(method("POST"))
+        .and(path("/anilist/oauth/token"))
+        .and(body_partial_json(json!({
+            "grant_type": "refresh_token",
+            "refresh_token": "new-refresh-token"
+        })))
+        .respond_with(
+            ResponseTemplate::new(200).set_body_json(common::token_response("refreshed-token")),
+        )
+        .expect(1)
+        .mount(&server)
+        .await;
+
+    let mut expired = common::anilist_config();
+    expired.expires_at = 0;
+    expired.refresh_token = "new-refresh-token".to_string();
+    let mut toml_config = TomlConfig {
+        anilist: Some(expired),
+        api: Some(urls.clone()),
+        ..TomlConfig::default()
+    };
+    assert!(config::ensure_anilist_token(&mut toml_config)
+        .await
+        .unwrap());
+    let anilist = config::load_config().unwrap().anilist.unwrap();
+    assert_eq!(anilist.access_token, "refreshed-token");
+    assert!(anilist.expires_at > 0);
+
+    // refreshing an expired MAL token
+    Mock::given(method("POST"))
+        .and(path("/mal/oauth2/token"))
+        .and(body_string_contains(
+            "client_id=mal-client-id&client_secret=mal-secret&grant_type=refresh_token&refresh_token=mal-refresh-token",
+        ))
+        .respond_with(
+            ResponseTemplate::new(200).set_body_json(common::token_response("refreshed-mal-token")),
+        )
+        .expect(1)
+        .mount(&server)
+        .await;
+
+    let mut expired = common::mal_config();
+    expired.expires_at = 0;
+    expired.refresh_token = "mal-refresh-token".to_string();
+    toml_config.myanimelist = Some(expired);
+    assert!(config::ensure_mal_token(&mut toml_config).await.unwrap());
+    let mal = config::load_config().unwrap().myanimelist.unwrap();
+    assert_eq!(mal.access_token, "refreshed-mal-token");
+    assert_eq!(mal.refresh_token, "new-refresh-token");
+    assert!(mal.expires_at > 0);
+
+    // a refresh token MAL rejects means logging in again, and nothing is saved
+    Mock::given(method("POST"))
+        .and(path("/mal/oauth2/token"))
+        .and(body_string_contains("refresh_token=revoked"))
+        .respond_with(ResponseTemplate::new(400).set_body_json(json!({
+            "error": "invalid_grant",
+            "message": "The refresh token is invalid."
+        })))
+        .expect(1)
+        .mount(&server)
+        .await;
+
+    let mut revoked = common::mal_config();
+    revoked.expires_at = 0;
+    revoked.refresh_token = "revoked".to_string();
+    toml_config.myanimelist = Some(revoked);
+    assert!(!config::ensure_mal_token(&mut toml_config).await.unwrap());
+    let mal = config::load_config().unwrap().myanimelist.unwrap();
+    assert_eq!(mal.access_token, "refreshed-mal-token");
+
+    // a token AniList rejects before it expires is refreshed once
+    Mock::given(method("POST"))
+        .and(path("/graphql"))
+        .and(header("Authorization", "Bearer rejected-token"))
+        .respond_with(ResponseTemplate::new(200).set_body_json(json!({
+            "errors": [{"message": "Invalid token", "status": 400}]
+        })))
+        .expect(1)
+        .with_priority(1)
+        .mount(&server)
+        .await;
+    Mock::given(method("POST"))
+        .and(path("/anilist/oauth/token"))
+        .and(body_partial_json(
+            json!({"refresh_token": "early-refresh-token"}),
+        ))
+        .respond_with(
+            ResponseTemplate::new(200).set_body_json(common::token_response("replacement-token")),
+        )
+        .expect(1)
+        .mount(&server)
+        .await;
+
+    let mut rejected = common::anilist_config();
+    rejected.access_token = "rejected-token".to_string();
+    rejected.refresh_token = "early-refresh-token".to_string();
+    toml_config.anilist = Some(rejected);
+    assert!(config::check_anilist_login(&mut toml_config).await.unwrap());
+    let anilist = config::load_config().unwrap().anilist.unwrap();
+    assert_eq!(anilist.access_token, "replacement-token");
+
+    // the same for MAL, and when the refresh is rejected too it's time to log in again
+    Mock::given(method("GET"))
+        .and(path("/mal/v2/users/@me"))
+        .and(header("Authorization", "Bearer rejected-token"))
+        .respond_with(ResponseTemplate::new(401))
+        .expect(2)
+        .with_priority(1)
+        .mount(&server)
+        .await;
+    common::mount_mal_user(&server).await;
+    Mock::given(method("POST"))
+        .and(path("/mal/oauth2/token"))
+        .and(body_string_contains("refresh_token=early-refresh-token"))
+        .respond_with(
+            ResponseTemplate::new(200)
+                .set_body_json(common::token_response("replacement-mal-token")),
+        )
+        .expect(1)
+        .mount(&server)
+        .await;
+
+    let mut rejected = common::mal_config();
+    rejected.access_token = "rejected-token".to_string();
+    rejected.refresh_token = "early-refresh-token".to_string();
+    toml_config.myanimelist = Some(rejected);
+    assert!(config::check_mal_login(&mut toml_config).await.unwrap());
+    let mal = config::load_config().unwrap().myanimelist.unwrap();
+    assert_eq!(mal.access_token, "replacement-mal-token");
+
+    Mock::given(method("POST"))
+        .and(path("/mal/oauth2/token"))
+        .and(body_string_contains("refresh_token=rejected-refresh-token"))
+        .respond_with(ResponseTemplate::new(400))
+        .expect(1)
+        .mount(&server)
+        .await;
+    let mut rejected = common::mal_config();
+    rejected.access_token = "rejected-token".to_string();
+    rejected.refresh_token = "rejected-refresh-token".to_string();
+    toml_config.myanimelist = Some(rejected);
+    assert!(!config::check_mal_login(&mut toml_config).await.unwrap());
+}
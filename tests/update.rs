@@ -0,0 +1,493 @@
+mod common;
+
+use std::fs;
+use std::io::Write;
+use std::path::Path;
+use std::process::{Command, Output, Stdio};
+
+use list_backup::config::TomlConfig;
+use wiremock::matchers::{
+    body_partial_json, body_string, body_string_contains, method, path, query_param,
+    query_param_is_missing,
+};
+use wiremock::{Mock, MockServer, ResponseTemplate};
+
+/// AniList has Show A completed more recently than MAL's copy, and Show B which isn't on MAL yet
+async fn mount_lists(server: &MockServer) {
+    Mock::given(method("GET"))
+        .and(path("/mal/v2/users/@me/animelist"))
+        .respond_with(ResponseTemplate::new(200).set_body_json(common::mal_page(
+            vec![common::mal_entry(
+                10,
+                "Show A",
+                "watching",
+                7,
+                3,
+                "2021-10-01T00:00:00+00:00",
+            )],
+            None,
+        )))
+        .mount(server)
+        .await;
+    Mock::given(method("POST"))
+        .and(path("/graphql"))
+        .and(body_string_contains("MediaListCollection"))
+        .respond_with(
+            ResponseTemplate::new(200).set_body_json(common::anilist_list(vec![
+                common::anilist_entry(1, Some(10), "Show A", "COMPLETED", 8.0, 12, 1633132800),
+                common::anilist_entry(2, Some(20), "Show B", "PLANNING", 0.0, 0, 1633132800),
+            ])),
+        )
+        .mount(server)
+        .await;
+    Mock::given(method("POST"))
+        .and(path("/graphql"))
+        .and(body_string_contains("SaveMediaListEntry"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(0)
+        .mount(server)
+        .await;
+}
+
+/// Runs the CLI with a config that's logged in to both services and uses the fake server.
+/// `input` is what gets typed in answer to its questions
+fn run(server: &MockServer, home: &Path, args: &[&str], input: &str) -> Output {
+    let config = TomlConfig {
+        anilist: Some(common::anilist_config()),
+        myanimelist: Some(common::mal_config()),
+        api: Some(common::urls(server)),
+        ..TomlConfig::default()
+    };
+    run_with_config(&config, home, args, input)
+}
+
+fn run_with_config(config: &TomlConfig, home: &Path, args: &[&str], input: &str) -> Output {
+    common::write_config(home, config);
+
+    let mut child = Command::new(env!("CARGO_BIN_EXE_list-backup"))
+        .args(args)
+        .env("HOME", home)
+        .env("ANILIST_SECRET", "anilist-secret")
+        .env("MAL_CLIENT_ID", "mal-client-id")
+        .env("MAL_SECRET", "mal-secret")
+        .current_dir(home)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .unwrap();
+    child
+        .stdin
+        .take()
+        .unwrap()
+        .write_all(input.as_bytes())
+        .unwrap();
+    child.wait_with_output().unwrap()
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn update_pushes_anilist_to_mal() {
+    let server = MockServer::start().await;
+    common::mount_score_format(&server, "POINT_10").await;
+    common::mount_mal_user(&server).await;
+    mount_lists(&server).await;
+    Mock::given(method("PATCH"))
+        .and(path("/mal/v2/anime/10/my_list_status"))
+        .and(body_string(
+            "status=completed&score=8&num_watched_episodes=12&is_rewatching=false&num_times_rewatched=0&start_date=&finish_date=&comments=",
+        ))
+        .respond_with(ResponseTemplate::new(200).set_body_string("{}"))
+        .expect(1)
+        .mount(&server)
+        .await;
+    Mock::given(method("PATCH"))
+        .and(path("/mal/v2/anime/20/my_list_status"))
+        .and(body_string(
+            "status=plan_to_watch&score=0&num_watched_episodes=0&is_rewatching=false&num_times_rewatched=0&start_date=&finish_date=&comments=",
+        ))
+        .respond_with(ResponseTemplate::new(200).set_body_string("{}"))
+        .expect(1)
+        .mount(&server)
+        .await;
+
+    let home = common::temp_home("update");
+    let output = run(&server, &home, &["update", "anime", "--yes"], "");
+
+    assert!(
+        output.status.success(),
+        "{}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn dry_run_changes_nothing() {
+    let server = MockServer::start().await;
+    common::mount_score_format(&server, "POINT_10").await;
+    common::mount_mal_user(&server).await;
+    mount_lists(&server).await;
+    Mock::given(method("PATCH"))
+        .respond_with(ResponseTemplate::new(200).set_body_string("{}"))
+        .expect(0)
+        .mount(&server)
+        .await;
+
+    let home = common::temp_home("dry-run");
+    let output = run(
+        &server,
+        &home,
+        &["update", "anime", "--dry-run", "--json"],
+        "",
+    );
+
+    assert!(output.status.success());
+    let plan: serde_json::Value = serde_json::from_slice(&output.stdout).unwrap();
+    let changes = plan["changes"].as_array().unwrap();
+    assert_eq!(changes.len(), 2);
+    assert_eq!(changes[0]["action"], "update");
+    assert_eq!(changes[0]["target"], "mal");
+    assert_eq!(changes[1]["action"], "create");
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn unlinked_entries_are_matched_once() {
+    let server = MockServer::start().await;
+    common::mount_score_format(&server, "POINT_10").await;
+    common::mount_mal_user(&server).await;
+    Mock::given(method("GET"))
+        .and(path("/mal/v2/users/@me/animelist"))
+        .respond_with(ResponseTemplate::new(200).set_body_json(common::mal_page(vec![], None)))
+        .mount(&server)
+        .await;
+    Mock::given(method("POST"))
+        .and(path("/graphql"))
+        .and(body_string_contains("MediaListCollection"))
+        .respond_with(
+            ResponseTemplate::new(200).set_body_json(common::anilist_list(vec![
+                common::anilist_entry(3, None, "Show C", "CURRENT", 7.0, 2, 1633132800),
+            ])),
+        )
+        .mount(&server)
+        .await;
+    // the TV series from the same year should be offered first
+    Mock::given(method("GET"))
+        .and(path("/mal/v2/anime"))
+        .and(query_param("q", "Show C"))
+        .respond_with(ResponseTemplate::new(200).set_body_json(serde_json::json!({
+            "data": [
+                {"node": {"id": 31, "title": "Show C Movie", "media_type": "movie", "start_date": "2022-05-01"}},
+                {"node": {"id": 30, "title": "Show C", "media_type": "tv", "start_date": "2021-10"}}
+            ]
+        })))
+        .expect(1)
+        .mount(&server)
+        .await;
+    Mock::given(method("PATCH"))
+        .and(path("/mal/v2/anime/30/my_list_status"))
+        .respond_with(ResponseTemplate::new(200).set_body_string("{}"))
+        .expect(2)
+        .mount(&server)
+        .await;
+
+    let home = common::temp_home("resolve");
+    // pick the first match, then confirm the update
+    let output = run(&server, &home, &["update", "anime"], "1\ny\n");
+    assert!(output.status.success());
+    let resolved = fs::read_to_string(home.join(".config/list-backup/resolved_ids.toml")).unwrap();
+    assert!(resolved.contains("mal_id = 30"));
+
+    // the second time the saved match is used without searching again
+    let output = run(&server, &home, &["update", "anime", "--yes"], "");
+    assert!(output.status.success());
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn mappings_win_over_anilist() {
+    let server = MockServer::start().await;
+    // scores are converted from the user's AniList score format
+    common::mount_score_format(&server, "POINT_100").await;
+    common::mount_mal_user(&server).await;
+    Mock::given(method("GET"))
+        .and(path("/mal/v2/users/@me/animelist"))
+        .respond_with(ResponseTemplate::new(200).set_body_json(common::mal_page(vec![], None)))
+        .mount(&server)
+        .await;
+    Mock::given(method("POST"))
+        .and(path("/graphql"))
+        .and(body_string_contains("MediaListCollection"))
+        .respond_with(
+            ResponseTemplate::new(200).set_body_json(common::anilist_list(vec![
+                common::anilist_entry(1, Some(10), "Show A", "CURRENT", 85.0, 2, 1633132800),
+                common::anilist_entry(2, Some(20), "Show B", "CURRENT", 85.0, 2, 1633132800),
+            ])),
+        )
+        .mount(&server)
+        .await;
+    Mock::given(method("PATCH"))
+        .and(path("/mal/v2/anime/11/my_list_status"))
+        .and(body_string(
+            "status=watching&score=8&num_watched_episodes=2&is_rewatching=false&num_times_rewatched=0&start_date=&finish_date=&comments=",
+        ))
+        .respond_with(ResponseTemplate::new(200).set_body_string("{}"))
+        .expect(1)
+        .mount(&server)
+        .await;
+    Mock::given(method("PATCH"))
+        .respond_with(ResponseTemplate::new(200).set_body_string("{}"))
+        .expect(0)
+        .mount(&server)
+        .await;
+
+    let home = common::temp_home("mappings");
+    let config_dir = home.join(".config").join("list-backup");
+    fs::create_dir_all(&config_dir).unwrap();
+    fs::write(
+        config_dir.join("mappings.toml"),
+        "[[override]]\nanilist_id = 1\nmal_id = 11\n\n[[block]]\nanilist_id = 2\nmal_id = 20\n",
+    )
+    .unwrap();
+    let output = run(&server, &home, &["update", "anime", "--yes"], "");
+
+    assert!(output.status.success());
+    assert!(String::from_utf8_lossy(&output.stderr).contains("Couldn't find Show B on MAL"));
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn every_mal_page_is_fetched() {
+    let server = MockServer::start().await;
+    common::mount_score_format(&server, "POINT_10").await;
+    common::mount_mal_user(&server).await;
+    mount_lists(&server).await;
+    // MAL's copy of Show A, already the same as AniList's, is on the last of three pages
+    let list_url = format!("{}/mal/v2/users/@me/animelist", server.uri());
+    let pages = [
+        (
+            None,
+            common::mal_entry(30, "Show C", "watching", 7, 3, "2021-10-01T00:00:00+00:00"),
+        ),
+        (
+            Some("1"),
+            common::mal_entry(40, "Show D", "watching", 7, 3, "2021-10-01T00:00:00+00:00"),
+        ),
+        (
+            Some("2"),
+            common::mal_entry(
+                10,
+                "Show A",
+                "completed",
+                8,
+                12,
+                "2021-10-01T00:00:00+00:00",
+            ),
+        ),
+    ];
+    for (i, (offset, entry)) in IntoIterator::into_iter(pages).enumerate() {
+        let next = match i {
+            0 | 1 => Some(format!("{}?offset={}&limit=1", list_url, i + 1)),
+            _ => None,
+        };
+        let mock = Mock::given(method("GET"))
+            .and(path("/mal/v2/users/@me/animelist"))
+            .and(query_param("limit", "1"));
+        let mock = match offset {
+            Some(offset) => mock.and(query_param("offset", offset)),
+            None => mock.and(query_param_is_missing("offset")),
+        };
+        mock.respond_with(
+            ResponseTemplate::new(200).set_body_json(common::mal_page(vec![entry], next)),
+        )
+        .expect(1)
+        // ahead of the single page `mount_lists` answers with
+        .with_priority(1)
+        .mount(&server)
+        .await;
+    }
+
+    let home = common::temp_home("paging");
+    let output = run(
+        &server,
+        &home,
+        &["update", "anime", "--page-size", "1", "--dry-run", "--json"],
+        "",
+    );
+
+    assert!(
+        output.status.success(),
+        "{}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+    let plan: serde_json::Value = serde_json::from_slice(&output.stdout).unwrap();
+    // only Show B is missing from MAL, so Show A was found on the last page
+    let changes = plan["changes"].as_array().unwrap();
+    assert_eq!(changes.len(), 1);
+    assert_eq!(changes[0]["title"], "Show B");
+    assert_eq!(changes[0]["action"], "create");
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn expired_logins_fail_without_a_terminal() {
+    let server = MockServer::start().await;
+    // both refresh tokens are rejected
+    for token_path in ["/anilist/oauth/token", "/mal/oauth2/token"] {
+        Mock::given(method("POST"))
+            .and(path(token_path))
+            .respond_with(ResponseTemplate::new(400))
+            .mount(&server)
+            .await;
+    }
+    let mut anilist = common::anilist_config();
+    anilist.expires_at = 0;
+    let mut mal = common::mal_config();
+    mal.expires_at = 0;
+    let config = TomlConfig {
+        anilist: Some(anilist),
+        myanimelist: Some(mal),
+        api: Some(common::urls(&server)),
+        ..TomlConfig::default()
+    };
+    let home = common::temp_home("expired-login");
+
+    // stdin isn't a terminal, so none of these wait for a login in the browser
+    for args in [
+        &["update", "anime", "--yes"][..],
+        &["update", "anime", "--dry-run", "--json"],
+        &["backup", "anime"],
+    ] {
+        let output = run_with_config(&config, &home, args, "");
+        assert_eq!(output.status.code(), Some(6), "{:?}", args);
+        assert!(String::from_utf8_lossy(&output.stderr).contains("to log in"));
+    }
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn sync_pushes_newer_mal_entries_to_anilist() {
+    let server = MockServer::start().await;
+    common::mount_score_format(&server, "POINT_10").await;
+    common::mount_mal_user(&server).await;
+    // watched two more episodes on MAL after AniList was last updated
+    Mock::given(method("GET"))
+        .and(path("/mal/v2/users/@me/animelist"))
+        .respond_with(ResponseTemplate::new(200).set_body_json(common::mal_page(
+            vec![common::mal_entry(
+                10,
+                "Show A",
+                "watching",
+                7,
+                5,
+                "2021-10-03T00:00:00+00:00",
+            )],
+            None,
+        )))
+        .mount(&server)
+        .await;
+    Mock::given(method("POST"))
+        .and(path("/graphql"))
+        .and(body_string_contains("MediaListCollection"))
+        .respond_with(
+            ResponseTemplate::new(200).set_body_json(common::anilist_list(vec![
+                common::anilist_entry(1, Some(10), "Show A", "CURRENT", 7.0, 3, 1633132800),
+            ])),
+        )
+        .mount(&server)
+        .await;
+    Mock::given(method("POST"))
+        .and(path("/graphql"))
+        .and(body_string_contains("SaveMediaListEntry"))
+        .and(body_partial_json(serde_json::json!({
+            "variables": {"media_id": 1, "status": "CURRENT", "score": 7.0, "progress": 5}
+        })))
+        .respond_with(ResponseTemplate::new(200).set_body_json(serde_json::json!({
+            "data": {"SaveMediaListEntry": {"id": 5}}
+        })))
+        .expect(1)
+        .mount(&server)
+        .await;
+    Mock::given(method("PATCH"))
+        .respond_with(ResponseTemplate::new(200).set_body_string("{}"))
+        .expect(0)
+        .mount(&server)
+        .await;
+
+    let home = common::temp_home("sync");
+    let output = run(&server, &home, &["sync", "anime", "--yes"], "");
+    assert!(
+        output.status.success(),
+        "{}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+
+    // a dry run only shows the change, the expectation above checks it was made once
+    let output = run(
+        &server,
+        &home,
+        &[
+            "sync",
+            "anime",
+            "--direction",
+            "mal-to-anilist",
+            "--dry-run",
+            "--json",
+        ],
+        "",
+    );
+    let plan: serde_json::Value = serde_json::from_slice(&output.stdout).unwrap();
+    assert_eq!(plan["changes"][0]["target"], "anilist");
+    assert_eq!(plan["changes"][0]["before"]["progress"], 3);
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn saved_plans_are_applied_as_they_are() {
+    let server = MockServer::start().await;
+    common::mount_score_format(&server, "POINT_10").await;
+    common::mount_mal_user(&server).await;
+    mount_lists(&server).await;
+
+    let home = common::temp_home("plan-file");
+    let output = run(
+        &server,
+        &home,
+        &["update", "anime", "--dry-run", "--plan-file", "plan.json"],
+        "",
+    );
+    assert!(output.status.success());
+    assert!(home.join("plan.json").exists());
+
+    // the lists aren't fetched again, so the plan is applied even if they've changed since
+    let server = MockServer::start().await;
+    common::mount_score_format(&server, "POINT_10").await;
+    common::mount_mal_user(&server).await;
+    Mock::given(method("GET"))
+        .and(path("/mal/v2/users/@me/animelist"))
+        .respond_with(ResponseTemplate::new(500))
+        .expect(0)
+        .mount(&server)
+        .await;
+    for id in &[10, 20] {
+        Mock::given(method("PATCH"))
+            .and(path(format!("/mal/v2/anime/{}/my_list_status", id)))
+            .respond_with(ResponseTemplate::new(200).set_body_string("{}"))
+            .expect(1)
+            .mount(&server)
+            .await;
+    }
+
+    let output = run(
+        &server,
+        &home,
+        &["update", "manga", "--plan-file", "plan.json", "--yes"],
+        "",
+    );
+    assert_eq!(output.status.code(), Some(2));
+
+    let output = run(
+        &server,
+        &home,
+        &["update", "anime", "--plan-file", "plan.json", "--yes"],
+        "",
+    );
+    assert!(
+        output.status.success(),
+        "{}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+}